@@ -0,0 +1,132 @@
+use std::collections::VecDeque;
+
+use image::{DynamicImage, ImageBuffer, Pixel, Primitive, Rgba};
+use num_traits::NumCast;
+
+pub fn premultiply(img: &mut DynamicImage) {
+    match img {
+        DynamicImage::ImageRgba8(buf) => premultiply_buffer(buf),
+        DynamicImage::ImageRgba16(buf) => premultiply_buffer(buf),
+        DynamicImage::ImageRgba32F(buf) => premultiply_buffer(buf),
+        _ => (),
+    }
+}
+
+pub fn bleed(img: &mut DynamicImage) {
+    match img {
+        DynamicImage::ImageRgba8(buf) => bleed_buffer(buf),
+        DynamicImage::ImageRgba16(buf) => bleed_buffer(buf),
+        DynamicImage::ImageRgba32F(buf) => bleed_buffer(buf),
+        _ => (),
+    }
+}
+
+fn premultiply_buffer<T: Primitive>(buf: &mut ImageBuffer<Rgba<T>, Vec<T>>)
+where
+    Rgba<T>: Pixel<Subpixel = T>,
+{
+    let max = T::DEFAULT_MAX_VALUE.to_f32().unwrap_or(1.0);
+    let integer = max > 1.0;
+    for pixel in buf.pixels_mut() {
+        let alpha = pixel[3].to_f32().unwrap_or(0.0) / max;
+        for c in 0..3 {
+            let v = pixel[c].to_f32().unwrap_or(0.0) * alpha;
+            let v = if integer { v.round() } else { v };
+            pixel[c] = NumCast::from(v).unwrap_or(T::DEFAULT_MIN_VALUE);
+        }
+    }
+}
+
+// every fully transparent pixel takes the colour of the closest fully opaque
+// pixel, found with a breadth first flood outwards from the opaque pixels.
+// Partly transparent pixels keep their own colour
+fn bleed_buffer<T: Primitive>(buf: &mut ImageBuffer<Rgba<T>, Vec<T>>)
+where
+    Rgba<T>: Pixel<Subpixel = T>,
+{
+    let (width, height) = (buf.width() as i64, buf.height() as i64);
+    let mut source = vec![None; (width * height) as usize];
+    let mut queue = VecDeque::new();
+
+    for (x, y, pixel) in buf.enumerate_pixels() {
+        if pixel[3] == T::DEFAULT_MAX_VALUE {
+            let i = (y as i64 * width + x as i64) as usize;
+            source[i] = Some((x, y));
+            queue.push_back((x as i64, y as i64));
+        }
+    }
+
+    while let Some((x, y)) = queue.pop_front() {
+        let nearest = source[(y * width + x) as usize];
+        for (dx, dy) in [(1, 0), (-1, 0), (0, 1), (0, -1)] {
+            let (nx, ny) = (x + dx, y + dy);
+            if nx < 0 || ny < 0 || nx >= width || ny >= height {
+                continue;
+            }
+            let n = (ny * width + nx) as usize;
+            if source[n].is_none() {
+                source[n] = nearest;
+                queue.push_back((nx, ny));
+            }
+        }
+    }
+
+    for y in 0..height as u32 {
+        for x in 0..width as u32 {
+            if buf.get_pixel(x, y)[3] > T::DEFAULT_MIN_VALUE {
+                continue;
+            }
+            if let Some((sx, sy)) = source[(y as i64 * width + x as i64) as usize] {
+                let colour = *buf.get_pixel(sx, sy);
+                let pixel = buf.get_pixel_mut(x, y);
+                for c in 0..3 {
+                    pixel[c] = colour[c];
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn premultiply_scales_colour_by_alpha() {
+        let mut img = DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(
+            1,
+            1,
+            image::Rgba([255, 128, 10, 128]),
+        ));
+        premultiply(&mut img);
+        assert_eq!(
+            img.as_rgba8().unwrap().get_pixel(0, 0),
+            &image::Rgba([128, 64, 5, 128])
+        );
+    }
+
+    #[test]
+    fn bleed_fills_colour_and_keeps_alpha() {
+        let mut buf = image::RgbaImage::new(5, 1);
+        buf.put_pixel(0, 0, image::Rgba([255, 0, 0, 255]));
+        buf.put_pixel(4, 0, image::Rgba([0, 0, 255, 255]));
+        let mut img = DynamicImage::ImageRgba8(buf);
+        bleed(&mut img);
+        let buf = img.as_rgba8().unwrap();
+        assert_eq!(buf.get_pixel(1, 0), &image::Rgba([255, 0, 0, 0]));
+        assert_eq!(buf.get_pixel(3, 0), &image::Rgba([0, 0, 255, 0]));
+        assert_eq!(buf.get_pixel(4, 0), &image::Rgba([0, 0, 255, 255]));
+    }
+
+    #[test]
+    fn bleed_comes_from_opaque_pixels_only() {
+        let mut buf = image::RgbaImage::new(4, 1);
+        buf.put_pixel(0, 0, image::Rgba([255, 0, 0, 255]));
+        buf.put_pixel(3, 0, image::Rgba([0, 255, 0, 64]));
+        let mut img = DynamicImage::ImageRgba8(buf);
+        bleed(&mut img);
+        let buf = img.as_rgba8().unwrap();
+        assert_eq!(buf.get_pixel(2, 0), &image::Rgba([255, 0, 0, 0]));
+        assert_eq!(buf.get_pixel(3, 0), &image::Rgba([0, 255, 0, 64]));
+    }
+}
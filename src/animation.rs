@@ -0,0 +1,233 @@
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+use image::codecs::{gif::GifDecoder, png::PngDecoder, webp::WebPDecoder};
+use image::{AnimationDecoder, DynamicImage, ImageFormat};
+use regex::Regex;
+use serde::Deserialize;
+
+pub const SIDECAR_FILE: &str = "animations.toml";
+pub const DEFAULT_FRAME_PATTERN: &str = r"^(?P<name>.+)_(?P<frame>\d+)$";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Forward,
+    Reverse,
+    PingPong,
+    PingPongReverse,
+}
+
+impl std::fmt::Display for Direction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self {
+            Direction::Forward => write!(f, "forward"),
+            Direction::Reverse => write!(f, "reverse"),
+            Direction::PingPong => write!(f, "pingpong"),
+            Direction::PingPongReverse => write!(f, "pingpong_reverse"),
+        }
+    }
+}
+
+pub struct Animation {
+    pub name: String,
+    pub frames: Vec<String>,
+    pub durations: Vec<u32>,
+    pub direction: Option<Direction>,
+}
+
+#[derive(Deserialize)]
+struct Timing {
+    duration: Option<u32>,
+    durations: Option<Vec<u32>>,
+}
+
+// any two or more sprites whose names match the pattern with the same `name` capture
+// form an animation, ordered by the number in the `frame` capture
+pub fn detect(pattern: &Regex, sprite_names: &[&str]) -> Vec<Animation> {
+    let mut sequences: BTreeMap<String, Vec<(u64, String)>> = BTreeMap::new();
+    for sprite_name in sprite_names {
+        let Some(captures) = pattern.captures(sprite_name) else {
+            continue;
+        };
+        if let (Some(name), Some(frame)) = (captures.name("name"), captures.name("frame")) {
+            if let Ok(frame) = frame.as_str().parse::<u64>() {
+                sequences
+                    .entry(name.as_str().to_owned())
+                    .or_default()
+                    .push((frame, sprite_name.to_string()));
+            }
+        }
+    }
+
+    sequences
+        .into_iter()
+        .filter(|(_, frames)| frames.len() > 1)
+        .map(|(name, mut frames)| {
+            frames.sort();
+            Animation {
+                name,
+                frames: frames.into_iter().map(|(_, frame)| frame).collect(),
+                durations: vec![],
+                direction: None,
+            }
+        })
+        .collect()
+}
+
+pub fn load_durations(
+    input_dir: &Path,
+    animations: &mut [Animation],
+) -> Result<(), Box<dyn Error>> {
+    let path = input_dir.join(SIDECAR_FILE);
+    if !path.exists() {
+        return Ok(());
+    }
+    apply_durations(&fs::read_to_string(path)?, animations)
+}
+
+fn apply_durations(text: &str, animations: &mut [Animation]) -> Result<(), Box<dyn Error>> {
+    let timings: HashMap<String, Timing> = toml::from_str(text)?;
+    for (name, timing) in timings {
+        let Some(animation) = animations.iter_mut().find(|anim| anim.name == name) else {
+            println!("Warning: {SIDECAR_FILE} has timing for {name} but no frames matched it");
+            continue;
+        };
+        let durations = match (timing.durations, timing.duration) {
+            (Some(durations), _) => durations,
+            (None, Some(duration)) => vec![duration; animation.frames.len()],
+            (None, None) => continue,
+        };
+        if durations.len() != animation.frames.len() {
+            println!(
+                "Warning: {name} has {frames} frames but {durations} durations, durations ignored",
+                frames = animation.frames.len(),
+                durations = durations.len()
+            );
+            continue;
+        }
+        animation.durations = durations;
+    }
+    Ok(())
+}
+
+pub type TimedFrames = Vec<(DynamicImage, u32)>;
+
+// every composited frame of an animated gif, apng or webp with its delay in milliseconds,
+// or None when the file holds a single image
+pub fn decode_frames<'a, R: Read + 'a>(
+    reader: R,
+    format: ImageFormat,
+) -> Result<Option<TimedFrames>, Box<dyn Error>> {
+    let frames = match format {
+        ImageFormat::Gif => GifDecoder::new(reader)?.into_frames(),
+        ImageFormat::Png => {
+            let decoder = PngDecoder::new(reader)?;
+            if !decoder.is_apng() {
+                return Ok(None);
+            }
+            decoder.apng().into_frames()
+        }
+        ImageFormat::WebP => {
+            let decoder = WebPDecoder::new(reader)?;
+            if !decoder.has_animation() {
+                return Ok(None);
+            }
+            decoder.into_frames()
+        }
+        _ => return Ok(None),
+    };
+
+    let frames = frames.collect_frames()?;
+    if frames.len() < 2 {
+        return Ok(None);
+    }
+    Ok(Some(
+        frames
+            .into_iter()
+            .map(|frame| {
+                let (numer, denom) = frame.delay().numer_denom_ms();
+                let delay = (numer as f32 / denom.max(1) as f32).round() as u32;
+                (DynamicImage::ImageRgba8(frame.into_buffer()), delay)
+            })
+            .collect(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run_frames() -> Vec<Animation> {
+        let names = [
+            "run_10", "run_2", "run_0", "idle", "jump_1", "jump_0", "walk_1",
+        ];
+        detect(&Regex::new(DEFAULT_FRAME_PATTERN).unwrap(), &names)
+    }
+
+    #[test]
+    fn detect_sorts_frames_numerically() {
+        let animations = run_frames();
+        let names: Vec<&str> = animations.iter().map(|anim| anim.name.as_str()).collect();
+        assert_eq!(names, vec!["jump", "run"]);
+        assert_eq!(animations[1].frames, vec!["run_0", "run_2", "run_10"]);
+    }
+
+    #[test]
+    fn durations_from_sidecar() -> Result<(), Box<dyn Error>> {
+        let mut animations = run_frames();
+        apply_durations(
+            "[run]\ndurations = [100, 80, 120]\n[jump]\nduration = 50\n[swim]\nduration = 10\n",
+            &mut animations,
+        )?;
+        assert_eq!(animations[0].durations, vec![50, 50]);
+        assert_eq!(animations[1].durations, vec![100, 80, 120]);
+        Ok(())
+    }
+
+    #[test]
+    fn mismatched_durations_are_ignored() -> Result<(), Box<dyn Error>> {
+        let mut animations = run_frames();
+        apply_durations("[run]\ndurations = [100]\n", &mut animations)?;
+        assert!(animations[1].durations.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn decode_gif_frames() -> Result<(), Box<dyn Error>> {
+        let mut bytes = vec![];
+        {
+            let mut encoder = image::codecs::gif::GifEncoder::new(&mut bytes);
+            for (colour, delay) in [([255, 0, 0, 255], 100), ([0, 0, 255, 255], 40)] {
+                encoder.encode_frame(image::Frame::from_parts(
+                    image::RgbaImage::from_pixel(4, 4, image::Rgba(colour)),
+                    0,
+                    0,
+                    image::Delay::from_numer_denom_ms(delay, 1),
+                ))?;
+            }
+        }
+
+        let frames = decode_frames(std::io::Cursor::new(bytes), ImageFormat::Gif)?.unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].1, 100);
+        assert_eq!(frames[1].1, 40);
+        assert_eq!(
+            frames[1].0.as_rgba8().unwrap().get_pixel(2, 2),
+            &image::Rgba([0, 0, 255, 255])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn single_images_are_not_frames() -> Result<(), Box<dyn Error>> {
+        let mut bytes = std::io::Cursor::new(vec![]);
+        image::RgbaImage::new(2, 2).write_to(&mut bytes, ImageFormat::Png)?;
+        assert!(
+            decode_frames(std::io::Cursor::new(bytes.into_inner()), ImageFormat::Png)?.is_none()
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,900 @@
+use std::error::Error;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+use flate2::read::ZlibDecoder;
+use image::{DynamicImage, Rgba, RgbaImage};
+
+use crate::animation::{Animation, Direction};
+use crate::meta_data::{Borders, Pivot};
+
+const HEADER_MAGIC: u16 = 0xA5E0;
+const FRAME_MAGIC: u16 = 0xF1FA;
+
+const CHUNK_OLD_PALETTE: u16 = 0x0004;
+const CHUNK_LAYER: u16 = 0x2004;
+const CHUNK_CEL: u16 = 0x2005;
+const CHUNK_TAGS: u16 = 0x2018;
+const CHUNK_PALETTE: u16 = 0x2019;
+const CHUNK_SLICE: u16 = 0x2022;
+
+pub struct Sprite {
+    pub name: String,
+    pub img: DynamicImage,
+    pub pivot: Option<Pivot>,
+    pub borders: Option<Borders>,
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], Box<dyn Error>> {
+        if self
+            .pos
+            .checked_add(n)
+            .is_none_or(|end| end > self.bytes.len())
+        {
+            return Err("Unexpected end of aseprite file".into());
+        }
+        let bytes = &self.bytes[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn rest(&mut self) -> &'a [u8] {
+        let bytes = &self.bytes[self.pos.min(self.bytes.len())..];
+        self.pos = self.bytes.len();
+        bytes
+    }
+
+    fn skip(&mut self, n: usize) -> Result<(), Box<dyn Error>> {
+        self.take(n).map(|_| ())
+    }
+
+    fn byte(&mut self) -> Result<u8, Box<dyn Error>> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn word(&mut self) -> Result<u16, Box<dyn Error>> {
+        let b = self.take(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    fn short(&mut self) -> Result<i16, Box<dyn Error>> {
+        Ok(self.word()? as i16)
+    }
+
+    fn dword(&mut self) -> Result<u32, Box<dyn Error>> {
+        let b = self.take(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn long(&mut self) -> Result<i32, Box<dyn Error>> {
+        Ok(self.dword()? as i32)
+    }
+
+    fn string(&mut self) -> Result<String, Box<dyn Error>> {
+        let len = self.word()? as usize;
+        Ok(String::from_utf8_lossy(self.take(len)?).to_string())
+    }
+}
+
+struct Layer {
+    name: String,
+    visible: bool,
+    group: bool,
+    background: bool,
+    level: u16,
+    opacity: u8,
+    blend_mode: u16,
+}
+
+enum CelData {
+    Pixels {
+        width: u32,
+        height: u32,
+        pixels: Vec<u8>,
+    },
+    Linked(usize),
+}
+
+struct Cel {
+    layer: usize,
+    x: i32,
+    y: i32,
+    opacity: u8,
+    data: CelData,
+}
+
+struct Frame {
+    duration: u32,
+    cels: Vec<Cel>,
+}
+
+struct Tag {
+    name: String,
+    from: usize,
+    to: usize,
+    direction: Direction,
+}
+
+struct Slice {
+    name: String,
+    keys: Vec<SliceKey>,
+}
+
+struct SliceKey {
+    frame: usize,
+    x: i32,
+    y: i32,
+    center: Option<(i32, i32, u32, u32)>,
+    pivot: Option<(i32, i32)>,
+}
+
+struct AsepriteFile {
+    width: u32,
+    height: u32,
+    depth: u16,
+    transparent_index: u8,
+    layer_opacity: bool,
+    palette: Vec<[u8; 4]>,
+    layers: Vec<Layer>,
+    frames: Vec<Frame>,
+    tags: Vec<Tag>,
+    slices: Vec<Slice>,
+}
+
+fn parse(bytes: &[u8]) -> Result<AsepriteFile, Box<dyn Error>> {
+    let mut r = Reader { bytes, pos: 0 };
+    r.dword()?;
+    if r.word()? != HEADER_MAGIC {
+        return Err("Not an aseprite file".into());
+    }
+    let num_frames = r.word()?;
+    let width = r.word()? as u32;
+    let height = r.word()? as u32;
+    let depth = r.word()?;
+    let flags = r.dword()?;
+    r.skip(10)?;
+    let transparent_index = r.byte()?;
+    r.skip(99)?;
+
+    if ![8, 16, 32].contains(&depth) {
+        return Err(format!("Unsupported aseprite colour depth {depth}").into());
+    }
+
+    let mut file = AsepriteFile {
+        width,
+        height,
+        depth,
+        transparent_index,
+        layer_opacity: flags & 1 != 0,
+        palette: vec![],
+        layers: vec![],
+        frames: vec![],
+        tags: vec![],
+        slices: vec![],
+    };
+    let mut has_new_palette = false;
+
+    for _ in 0..num_frames {
+        let frame_start = r.pos;
+        let frame_bytes = r.dword()? as usize;
+        if r.word()? != FRAME_MAGIC {
+            return Err("Corrupt aseprite frame".into());
+        }
+        let old_chunks = r.word()?;
+        let duration = r.word()? as u32;
+        r.skip(2)?;
+        let new_chunks = r.dword()?;
+        let num_chunks = if new_chunks == 0 {
+            old_chunks as u32
+        } else {
+            new_chunks
+        };
+
+        let mut frame = Frame {
+            duration,
+            cels: vec![],
+        };
+        for _ in 0..num_chunks {
+            let size = r.dword()? as usize;
+            let kind = r.word()?;
+            let mut chunk = Reader {
+                bytes: r.take(size.saturating_sub(6))?,
+                pos: 0,
+            };
+            match kind {
+                CHUNK_LAYER => file.layers.push(parse_layer(&mut chunk)?),
+                CHUNK_CEL => {
+                    if let Some(cel) = parse_cel(&mut chunk, depth)? {
+                        frame.cels.push(cel);
+                    }
+                }
+                CHUNK_TAGS => file.tags.extend(parse_tags(&mut chunk)?),
+                CHUNK_PALETTE => {
+                    parse_palette(&mut chunk, &mut file.palette)?;
+                    has_new_palette = true;
+                }
+                CHUNK_OLD_PALETTE if !has_new_palette => {
+                    parse_old_palette(&mut chunk, &mut file.palette)?
+                }
+                CHUNK_SLICE => file.slices.push(parse_slice(&mut chunk)?),
+                _ => (),
+            }
+        }
+        file.frames.push(frame);
+        r.pos = frame_start + frame_bytes;
+    }
+    Ok(file)
+}
+
+fn parse_layer(r: &mut Reader) -> Result<Layer, Box<dyn Error>> {
+    let flags = r.word()?;
+    let kind = r.word()?;
+    let level = r.word()?;
+    r.skip(4)?;
+    let blend_mode = r.word()?;
+    let opacity = r.byte()?;
+    r.skip(3)?;
+    Ok(Layer {
+        name: r.string()?,
+        visible: flags & 1 != 0,
+        group: kind == 1,
+        background: flags & 8 != 0,
+        level,
+        opacity,
+        blend_mode,
+    })
+}
+
+fn parse_cel(r: &mut Reader, depth: u16) -> Result<Option<Cel>, Box<dyn Error>> {
+    let layer = r.word()? as usize;
+    let x = r.short()? as i32;
+    let y = r.short()? as i32;
+    let opacity = r.byte()?;
+    let kind = r.word()?;
+    r.skip(7)?;
+    let data = match kind {
+        0 | 2 => {
+            let width = r.word()? as u32;
+            let height = r.word()? as u32;
+            // the header can claim any size, so it's checked before reading
+            let len = (width as u64)
+                .checked_mul(height as u64)
+                .and_then(|area| area.checked_mul(depth as u64))
+                .and_then(|bits| usize::try_from(bits / 8).ok())
+                .ok_or("Corrupt aseprite cel, its size overflows")?;
+            let pixels = if kind == 0 {
+                r.take(len)?.to_vec()
+            } else {
+                let mut pixels = vec![];
+                ZlibDecoder::new(r.rest())
+                    .take(len as u64)
+                    .read_to_end(&mut pixels)?;
+                pixels
+            };
+            if pixels.len() < len {
+                return Err("Corrupt aseprite cel".into());
+            }
+            CelData::Pixels {
+                width,
+                height,
+                pixels,
+            }
+        }
+        1 => CelData::Linked(r.word()? as usize),
+        _ => {
+            println!("Warning: tilemap cels are not supported - skipping");
+            return Ok(None);
+        }
+    };
+    Ok(Some(Cel {
+        layer,
+        x,
+        y,
+        opacity,
+        data,
+    }))
+}
+
+fn parse_tags(r: &mut Reader) -> Result<Vec<Tag>, Box<dyn Error>> {
+    let num_tags = r.word()?;
+    r.skip(8)?;
+    let mut tags = vec![];
+    for _ in 0..num_tags {
+        let from = r.word()? as usize;
+        let to = r.word()? as usize;
+        let direction = match r.byte()? {
+            1 => Direction::Reverse,
+            2 => Direction::PingPong,
+            3 => Direction::PingPongReverse,
+            _ => Direction::Forward,
+        };
+        r.skip(12)?;
+        tags.push(Tag {
+            name: r.string()?,
+            from,
+            to,
+            direction,
+        });
+    }
+    Ok(tags)
+}
+
+fn parse_palette(r: &mut Reader, palette: &mut Vec<[u8; 4]>) -> Result<(), Box<dyn Error>> {
+    let size = r.dword()? as usize;
+    let first = r.dword()? as usize;
+    let last = r.dword()? as usize;
+    r.skip(8)?;
+    // the header can claim any size, so it's checked before growing the palette
+    if size > 65536 || last < first {
+        return Err("Corrupt aseprite palette".into());
+    }
+    palette.resize(size.max(palette.len()), [0, 0, 0, 0]);
+    for i in first..=last {
+        let flags = r.word()?;
+        let colour = r.take(4)?;
+        if i < palette.len() {
+            palette[i] = [colour[0], colour[1], colour[2], colour[3]];
+        }
+        if flags & 1 != 0 {
+            r.string()?;
+        }
+    }
+    Ok(())
+}
+
+fn parse_old_palette(r: &mut Reader, palette: &mut Vec<[u8; 4]>) -> Result<(), Box<dyn Error>> {
+    let packets = r.word()?;
+    let mut index = 0;
+    for _ in 0..packets {
+        index += r.byte()? as usize;
+        let count = match r.byte()? {
+            0 => 256,
+            n => n as usize,
+        };
+        for _ in 0..count {
+            let colour = r.take(3)?;
+            if index >= palette.len() {
+                palette.resize(index + 1, [0, 0, 0, 0]);
+            }
+            palette[index] = [colour[0], colour[1], colour[2], 255];
+            index += 1;
+        }
+    }
+    Ok(())
+}
+
+fn parse_slice(r: &mut Reader) -> Result<Slice, Box<dyn Error>> {
+    let num_keys = r.dword()?;
+    let flags = r.dword()?;
+    r.skip(4)?;
+    let name = r.string()?;
+    let mut keys = vec![];
+    for _ in 0..num_keys {
+        let mut key = SliceKey {
+            frame: r.dword()? as usize,
+            x: r.long()?,
+            y: r.long()?,
+            center: None,
+            pivot: None,
+        };
+        r.skip(8)?;
+        if flags & 1 != 0 {
+            key.center = Some((r.long()?, r.long()?, r.dword()?, r.dword()?));
+        }
+        if flags & 2 != 0 {
+            key.pivot = Some((r.long()?, r.long()?));
+        }
+        keys.push(key);
+    }
+    // the latest key at or before a frame applies to it
+    keys.sort_by_key(|key| key.frame);
+    Ok(Slice { name, keys })
+}
+
+// non premultiplied source over destination
+fn blend(dst: &mut Rgba<u8>, src: [u8; 4], opacity: f32) {
+    let sa = src[3] as f32 / 255.0 * opacity;
+    if sa <= 0.0 {
+        return;
+    }
+    let da = dst[3] as f32 / 255.0;
+    let out_a = sa + da * (1.0 - sa);
+    for c in 0..3 {
+        let v = (src[c] as f32 * sa + dst[c] as f32 * da * (1.0 - sa)) / out_a;
+        dst[c] = v.round() as u8;
+    }
+    dst[3] = (out_a * 255.0).round() as u8;
+}
+
+impl AsepriteFile {
+    fn colour(&self, pixels: &[u8], i: usize, background: bool) -> [u8; 4] {
+        match self.depth {
+            32 => [
+                pixels[i * 4],
+                pixels[i * 4 + 1],
+                pixels[i * 4 + 2],
+                pixels[i * 4 + 3],
+            ],
+            16 => [
+                pixels[i * 2],
+                pixels[i * 2],
+                pixels[i * 2],
+                pixels[i * 2 + 1],
+            ],
+            _ => {
+                let index = pixels[i];
+                if index == self.transparent_index && !background {
+                    [0, 0, 0, 0]
+                } else {
+                    self.palette
+                        .get(index as usize)
+                        .cloned()
+                        .unwrap_or([0, 0, 0, 0])
+                }
+            }
+        }
+    }
+
+    fn cel(&self, frame: usize, layer: usize) -> Option<&Cel> {
+        let cel = self.frames[frame]
+            .cels
+            .iter()
+            .find(|cel| cel.layer == layer)?;
+        match cel.data {
+            CelData::Pixels { .. } => Some(cel),
+            CelData::Linked(linked) if linked < frame => self.cel(linked, layer),
+            CelData::Linked(_) => None,
+        }
+    }
+
+    // layers hidden directly or through a hidden group are left out
+    fn visible_layers(&self) -> Vec<usize> {
+        let mut parents: Vec<bool> = vec![];
+        let mut visible = vec![];
+        for (i, layer) in self.layers.iter().enumerate() {
+            parents.truncate(layer.level as usize);
+            let shown = layer.visible && parents.iter().all(|parent| *parent);
+            if layer.group {
+                parents.push(shown);
+            } else if shown {
+                visible.push(i);
+            }
+        }
+        visible
+    }
+
+    fn render(&self, frame: usize, layers: &[usize]) -> RgbaImage {
+        let mut img = RgbaImage::new(self.width, self.height);
+        for &layer_index in layers {
+            let layer = &self.layers[layer_index];
+            let Some(cel) = self.cel(frame, layer_index) else {
+                continue;
+            };
+            let CelData::Pixels {
+                width,
+                height,
+                pixels,
+            } = &cel.data
+            else {
+                continue;
+            };
+            let layer_opacity = if self.layer_opacity {
+                layer.opacity
+            } else {
+                255
+            };
+            let opacity = cel.opacity as f32 / 255.0 * layer_opacity as f32 / 255.0;
+            for cy in 0..*height {
+                for cx in 0..*width {
+                    let (x, y) = (cel.x + cx as i32, cel.y + cy as i32);
+                    if x < 0 || y < 0 || x >= self.width as i32 || y >= self.height as i32 {
+                        continue;
+                    }
+                    let colour = self.colour(pixels, (cy * width + cx) as usize, layer.background);
+                    blend(img.get_pixel_mut(x as u32, y as u32), colour, opacity);
+                }
+            }
+        }
+        img
+    }
+
+    // the slice named after the layer, or after the file when layers aren't
+    // split. A file with a single slice applies it to every sprite
+    fn slice(&self, name: &str) -> Option<&Slice> {
+        self.slices
+            .iter()
+            .find(|slice| slice.name == name)
+            .or(match self.slices.as_slice() {
+                [slice] => Some(slice),
+                _ => None,
+            })
+    }
+
+    fn slice_key(slice: Option<&Slice>, frame: usize) -> Option<&SliceKey> {
+        slice?.keys.iter().rev().find(|key| key.frame <= frame)
+    }
+
+    fn pivot(key: Option<&SliceKey>) -> Option<Pivot> {
+        let key = key?;
+        let (px, py) = key.pivot?;
+        Some(Pivot {
+            x: (key.x + px) as f32,
+            y: (key.y + py) as f32,
+        })
+    }
+
+    // the centre of a nine patch slice, as distances from the edges of the sprite
+    fn borders(&self, key: Option<&SliceKey>) -> Option<Borders> {
+        let key = key?;
+        let (cx, cy, cw, ch) = key.center?;
+        let (left, top) = (key.x + cx, key.y + cy);
+        let (right, bottom) = (left + cw as i32, top + ch as i32);
+        Some(Borders {
+            left: left.max(0) as u32,
+            top: top.max(0) as u32,
+            right: (self.width as i32 - right).max(0) as u32,
+            bottom: (self.height as i32 - bottom).max(0) as u32,
+        })
+    }
+
+    fn sprites(&self, stem: &str, split_layers: bool) -> (Vec<Sprite>, Vec<Animation>) {
+        let visible = self.visible_layers();
+        for &i in &visible {
+            if self.layers[i].blend_mode != 0 {
+                println!(
+                    "Warning: {stem} layer {layer} uses a blend mode other than normal, drawn as normal",
+                    layer = self.layers[i].name
+                );
+            }
+        }
+        let groups: Vec<(String, &str, Vec<usize>)> = if split_layers {
+            visible
+                .iter()
+                .map(|&i| {
+                    let layer = self.layers[i].name.as_str();
+                    (format!("{stem}_{layer}"), layer, vec![i])
+                })
+                .collect()
+        } else {
+            vec![(stem.to_owned(), stem, visible)]
+        };
+
+        let durations: Vec<u32> = self.frames.iter().map(|frame| frame.duration).collect();
+        let mut sprites = vec![];
+        let mut animations = vec![];
+        for (prefix, slice_name, layers) in groups {
+            let slice = self.slice(slice_name);
+            let names: Vec<String> = (0..self.frames.len())
+                .map(|i| {
+                    if self.frames.len() == 1 {
+                        prefix.clone()
+                    } else {
+                        format!("{prefix}_{i}")
+                    }
+                })
+                .collect();
+            for (i, name) in names.iter().enumerate() {
+                let key = Self::slice_key(slice, i);
+                sprites.push(Sprite {
+                    name: name.clone(),
+                    img: DynamicImage::ImageRgba8(self.render(i, &layers)),
+                    pivot: Self::pivot(key),
+                    borders: self.borders(key),
+                });
+            }
+
+            if self.tags.is_empty() && names.len() > 1 {
+                animations.push(Animation {
+                    name: prefix.clone(),
+                    frames: names.clone(),
+                    durations: durations.clone(),
+                    direction: None,
+                });
+            }
+            for tag in &self.tags {
+                if tag.from > tag.to || tag.to >= names.len() {
+                    println!(
+                        "Warning: {stem} tag {} is out of range - skipping",
+                        tag.name
+                    );
+                    continue;
+                }
+                animations.push(Animation {
+                    name: format!("{prefix}_{}", tag.name),
+                    frames: names[tag.from..=tag.to].to_vec(),
+                    durations: durations[tag.from..=tag.to].to_vec(),
+                    direction: Some(tag.direction),
+                });
+            }
+        }
+        (sprites, animations)
+    }
+}
+
+pub fn is_aseprite(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|ext| ext.to_str()),
+        Some("ase") | Some("aseprite")
+    )
+}
+
+pub fn import(
+    path: &Path,
+    stem: &str,
+    split_layers: bool,
+) -> Result<(Vec<Sprite>, Vec<Animation>), Box<dyn Error>> {
+    let file = parse(&fs::read(path)?)?;
+    Ok(file.sprites(stem, split_layers))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn string(s: &str) -> Vec<u8> {
+        let mut bytes = (s.len() as u16).to_le_bytes().to_vec();
+        bytes.extend(s.as_bytes());
+        bytes
+    }
+
+    fn chunk(kind: u16, data: Vec<u8>) -> Vec<u8> {
+        let mut bytes = ((data.len() + 6) as u32).to_le_bytes().to_vec();
+        bytes.extend(kind.to_le_bytes());
+        bytes.extend(data);
+        bytes
+    }
+
+    fn layer(name: &str, flags: u16, kind: u16, level: u16, opacity: u8) -> Vec<u8> {
+        let mut data = vec![];
+        for word in [flags, kind, level, 0, 0, 0] {
+            data.extend(word.to_le_bytes());
+        }
+        data.extend([opacity, 0, 0, 0]);
+        data.extend(string(name));
+        chunk(CHUNK_LAYER, data)
+    }
+
+    fn cel_header(layer: u16, x: i16, y: i16, kind: u16) -> Vec<u8> {
+        let mut data = vec![];
+        data.extend(layer.to_le_bytes());
+        data.extend(x.to_le_bytes());
+        data.extend(y.to_le_bytes());
+        data.push(255);
+        data.extend(kind.to_le_bytes());
+        data.extend([0; 7]);
+        data
+    }
+
+    fn cel(layer: u16, x: i16, y: i16, size: u16, colour: [u8; 4], compressed: bool) -> Vec<u8> {
+        let mut data = cel_header(layer, x, y, if compressed { 2 } else { 0 });
+        data.extend(size.to_le_bytes());
+        data.extend(size.to_le_bytes());
+        let pixels: Vec<u8> = (0..size * size).flat_map(|_| colour).collect();
+        if compressed {
+            let mut encoder =
+                flate2::write::ZlibEncoder::new(vec![], flate2::Compression::default());
+            encoder.write_all(&pixels).unwrap();
+            data.extend(encoder.finish().unwrap());
+        } else {
+            data.extend(pixels);
+        }
+        chunk(CHUNK_CEL, data)
+    }
+
+    fn linked_cel(layer: u16, frame: u16) -> Vec<u8> {
+        let mut data = cel_header(layer, 0, 0, 1);
+        data.extend(frame.to_le_bytes());
+        chunk(CHUNK_CEL, data)
+    }
+
+    fn tags() -> Vec<u8> {
+        let mut data = 1u16.to_le_bytes().to_vec();
+        data.extend([0; 8]);
+        data.extend(0u16.to_le_bytes());
+        data.extend(1u16.to_le_bytes());
+        data.push(2);
+        data.extend([0; 12]);
+        data.extend(string("walk"));
+        chunk(CHUNK_TAGS, data)
+    }
+
+    // a slice over the whole 4x4 sprite with a centre and a pivot
+    fn slice(name: &str, pivot: (u32, u32)) -> Vec<u8> {
+        let mut data = vec![];
+        for dword in [1u32, 3, 0] {
+            data.extend(dword.to_le_bytes());
+        }
+        data.extend(string(name));
+        for dword in [0u32, 0, 0, 4, 4, 1, 1, 2, 1, pivot.0, pivot.1] {
+            data.extend(dword.to_le_bytes());
+        }
+        chunk(CHUNK_SLICE, data)
+    }
+
+    fn frame(duration: u16, chunks: Vec<Vec<u8>>) -> Vec<u8> {
+        let data: Vec<u8> = chunks.concat();
+        let mut bytes = ((data.len() + 16) as u32).to_le_bytes().to_vec();
+        bytes.extend(FRAME_MAGIC.to_le_bytes());
+        bytes.extend((chunks.len() as u16).to_le_bytes());
+        bytes.extend(duration.to_le_bytes());
+        bytes.extend([0, 0]);
+        bytes.extend((chunks.len() as u32).to_le_bytes());
+        bytes.extend(data);
+        bytes
+    }
+
+    fn file(frames: Vec<Vec<u8>>) -> Vec<u8> {
+        let data: Vec<u8> = frames.concat();
+        let mut bytes = ((data.len() + 128) as u32).to_le_bytes().to_vec();
+        bytes.extend(HEADER_MAGIC.to_le_bytes());
+        for word in [frames.len() as u16, 4, 4, 32] {
+            bytes.extend(word.to_le_bytes());
+        }
+        bytes.extend(1u32.to_le_bytes());
+        bytes.resize(128, 0);
+        bytes.extend(data);
+        bytes
+    }
+
+    const RED: [u8; 4] = [255, 0, 0, 255];
+    const GREEN: [u8; 4] = [0, 255, 0, 255];
+    const BLUE: [u8; 4] = [0, 0, 255, 255];
+
+    // body and top are visible, hidden is hidden and child is inside a hidden group
+    fn hero() -> AsepriteFile {
+        let bytes = file(vec![
+            frame(
+                100,
+                vec![
+                    layer("body", 1, 0, 0, 255),
+                    layer("hidden", 0, 0, 0, 255),
+                    layer("group", 0, 1, 0, 255),
+                    layer("child", 1, 0, 1, 255),
+                    layer("top", 1, 0, 0, 255),
+                    tags(),
+                    slice("slice", (2, 3)),
+                    cel(0, 0, 0, 2, RED, false),
+                    cel(1, 0, 0, 4, BLUE, false),
+                    cel(3, 0, 0, 4, BLUE, true),
+                    cel(4, 1, 1, 1, GREEN, true),
+                ],
+            ),
+            frame(50, vec![linked_cel(0, 0)]),
+        ]);
+        parse(&bytes).unwrap()
+    }
+
+    #[test]
+    fn flatten_visible_layers() {
+        let (sprites, animations) = hero().sprites("hero", false);
+        let names: Vec<&str> = sprites.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, vec!["hero_0", "hero_1"]);
+
+        let first = sprites[0].img.as_rgba8().unwrap();
+        assert_eq!(first.get_pixel(0, 0).0, RED);
+        assert_eq!(first.get_pixel(1, 1).0, GREEN);
+        assert_eq!(first.get_pixel(3, 3).0, [0, 0, 0, 0]);
+        let second = sprites[1].img.as_rgba8().unwrap();
+        assert_eq!(second.get_pixel(1, 1).0, RED);
+
+        assert_eq!(animations.len(), 1);
+        assert_eq!(animations[0].name, "hero_walk");
+        assert_eq!(animations[0].frames, vec!["hero_0", "hero_1"]);
+        assert_eq!(animations[0].durations, vec![100, 50]);
+        assert_eq!(animations[0].direction, Some(Direction::PingPong));
+    }
+
+    #[test]
+    fn slices_become_pivot_and_borders() {
+        let (sprites, _) = hero().sprites("hero", false);
+        assert_eq!(sprites[0].pivot, Some(Pivot { x: 2.0, y: 3.0 }));
+        assert_eq!(
+            sprites[0].borders,
+            Some(Borders {
+                left: 1,
+                top: 1,
+                right: 1,
+                bottom: 2,
+            })
+        );
+    }
+
+    #[test]
+    fn slices_apply_to_the_layer_they_are_named_after() {
+        let bytes = file(vec![frame(
+            100,
+            vec![
+                layer("body", 1, 0, 0, 255),
+                layer("top", 1, 0, 0, 255),
+                slice("body", (1, 1)),
+                slice("top", (2, 3)),
+                cel(0, 0, 0, 2, RED, false),
+                cel(1, 1, 1, 1, GREEN, false),
+            ],
+        )]);
+        let file = parse(&bytes).unwrap();
+        let (sprites, _) = file.sprites("hero", true);
+        let pivots: Vec<(&str, Option<Pivot>)> = sprites
+            .iter()
+            .map(|sprite| (sprite.name.as_str(), sprite.pivot))
+            .collect();
+        assert_eq!(
+            pivots,
+            vec![
+                ("hero_body", Some(Pivot { x: 1.0, y: 1.0 })),
+                ("hero_top", Some(Pivot { x: 2.0, y: 3.0 })),
+            ]
+        );
+
+        // neither slice is named after the file, and there's more than one
+        let (sprites, _) = file.sprites("hero", false);
+        assert_eq!(sprites[0].pivot, None);
+        assert_eq!(sprites[0].borders, None);
+    }
+
+    #[test]
+    fn split_layers() {
+        let (sprites, animations) = hero().sprites("hero", true);
+        let names: Vec<&str> = sprites.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec!["hero_body_0", "hero_body_1", "hero_top_0", "hero_top_1"]
+        );
+        assert_eq!(
+            sprites[2].img.as_rgba8().unwrap().get_pixel(0, 0).0,
+            [0, 0, 0, 0]
+        );
+        let names: Vec<&str> = animations.iter().map(|a| a.name.as_str()).collect();
+        assert_eq!(names, vec!["hero_body_walk", "hero_top_walk"]);
+    }
+
+    #[test]
+    fn oversized_cel_is_an_error() {
+        // a raw 65535x65535 cel at 32 bits a pixel with no pixels following
+        let mut bytes = vec![0u8; 16];
+        bytes.extend_from_slice(&[0xff, 0xff, 0xff, 0xff]);
+        let mut r = Reader {
+            bytes: &bytes,
+            pos: 0,
+        };
+        assert!(parse_cel(&mut r, 32).is_err());
+    }
+
+    #[test]
+    fn oversized_palette_is_an_error() {
+        let palette_header = |size: u32, first: u32, last: u32| -> Vec<u8> {
+            let mut bytes = vec![];
+            for dword in [size, first, last, 0, 0] {
+                bytes.extend(dword.to_le_bytes());
+            }
+            bytes
+        };
+        for bytes in [
+            palette_header(0xffff_fff0, 0, 0),
+            palette_header(4, 3, 1),
+            // a truncated chunk that stops short of its colours
+            palette_header(4, 0, 3),
+        ] {
+            let mut r = Reader {
+                bytes: &bytes,
+                pos: 0,
+            };
+            assert!(parse_palette(&mut r, &mut vec![]).is_err());
+        }
+    }
+
+    #[test]
+    fn blend_with_opacity() {
+        let mut dst = Rgba(RED);
+        blend(&mut dst, BLUE, 0.5);
+        assert_eq!(dst.0, [128, 0, 128, 255]);
+    }
+}
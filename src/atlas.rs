@@ -0,0 +1,407 @@
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use regex::Regex;
+use serde_json::Value;
+
+// how a sprite was turned to be stored in the sheet
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rotation {
+    None,
+    Clockwise,
+    CounterClockwise,
+}
+
+// a sprite in a sheet written by corral or another packer, width and height are
+// the size of the sprite before it was rotated into the sheet
+#[derive(Debug, PartialEq, Eq)]
+pub struct Region {
+    pub name: String,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub rotation: Rotation,
+    pub offset: (u32, u32),
+    pub source_size: (u32, u32),
+}
+
+impl Region {
+    fn new(name: String, x: u32, y: u32, width: u32, height: u32) -> Region {
+        Region {
+            name,
+            x,
+            y,
+            width,
+            height,
+            rotation: Rotation::None,
+            offset: (0, 0),
+            source_size: (width, height),
+        }
+    }
+
+    // the area the region covers in the sheet
+    pub fn sheet_size(&self) -> (u32, u32) {
+        match self.rotation {
+            Rotation::None => (self.width, self.height),
+            _ => (self.height, self.width),
+        }
+    }
+}
+
+// what the data says about the sheet as a whole, when the format records it
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Atlas {
+    pub regions: Vec<Region>,
+    pub size: Option<(u32, u32)>,
+    pub padding: Option<u32>,
+    pub premultiplied_alpha: bool,
+}
+
+// the names of one sprite written by any of the formats compare the same, Lua
+// upper-cases names, all of them replace spaces and TexturePacker keeps the extension
+pub fn normalise_name(name: &str) -> String {
+    name.trim_end_matches(".png")
+        .replace(' ', "_")
+        .to_lowercase()
+}
+
+pub fn read(data_file: &str) -> Result<Atlas, Box<dyn Error>> {
+    let text = fs::read_to_string(data_file)?;
+    let extension = Path::new(data_file)
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase());
+    match extension.as_deref() {
+        Some("json") => parse_json(&text),
+        Some("lua") => parse_lua(&text),
+        Some("atlas") => parse_libgdx(&text),
+        _ => Err(
+            format!("Unrecognised data file {data_file}, expected .json, .lua or .atlas").into(),
+        ),
+    }
+}
+
+fn number(value: &Value, key: &str) -> Result<u32, Box<dyn Error>> {
+    value[key]
+        .as_u64()
+        .map(|n| n as u32)
+        .ok_or_else(|| format!("Missing or invalid `{key}`").into())
+}
+
+fn parse_json(text: &str) -> Result<Atlas, Box<dyn Error>> {
+    let json: Value = serde_json::from_str(text)?;
+    let meta = &json["meta"];
+    let optional = |value: &Value, key: &str| value[key].as_u64().map(|n| n as u32);
+    let regions = match (&json, &json["sprites"], &json["frames"]) {
+        (Value::Array(sprites), _, _) | (_, Value::Array(sprites), _) => {
+            let regions = sprites
+                .iter()
+                .map(parse_corral_sprite)
+                .collect::<Result<Vec<Region>, _>>()?;
+            return Ok(Atlas {
+                regions,
+                size: optional(meta, "width").zip(optional(meta, "height")),
+                padding: optional(meta, "padding"),
+                premultiplied_alpha: meta["premultiplied_alpha"].as_bool() == Some(true),
+            });
+        }
+        (_, _, Value::Object(frames)) => frames
+            .iter()
+            .map(|(name, frame)| parse_texture_packer_frame(name, frame))
+            .collect::<Result<Vec<Region>, _>>()?,
+        (_, _, Value::Array(frames)) => frames
+            .iter()
+            .map(|frame| {
+                let name = frame["filename"].as_str().ok_or("Missing `filename`")?;
+                parse_texture_packer_frame(name, frame)
+            })
+            .collect::<Result<Vec<Region>, _>>()?,
+        _ => return Err("Unrecognised json sprite sheet data".into()),
+    };
+    Ok(Atlas {
+        regions,
+        size: optional(&meta["size"], "w").zip(optional(&meta["size"], "h")),
+        ..Default::default()
+    })
+}
+
+// corral turns rotated sprites clockwise too and keeps their unrotated size
+fn parse_corral_sprite(sprite: &Value) -> Result<Region, Box<dyn Error>> {
+    let mut region = Region::new(
+        sprite["name"].as_str().ok_or("Missing `name`")?.to_owned(),
+        number(sprite, "x")?,
+        number(sprite, "y")?,
+        number(sprite, "width")?,
+        number(sprite, "height")?,
+    );
+    if sprite["rotated"].as_bool() == Some(true) {
+        region.rotation = Rotation::Clockwise;
+    }
+    Ok(region)
+}
+
+// texture packer stores rotated sprites turned clockwise, the frame keeps the
+// unrotated size
+fn parse_texture_packer_frame(name: &str, frame: &Value) -> Result<Region, Box<dyn Error>> {
+    let rect = &frame["frame"];
+    let mut region = Region::new(
+        name.to_owned(),
+        number(rect, "x")?,
+        number(rect, "y")?,
+        number(rect, "w")?,
+        number(rect, "h")?,
+    );
+    if frame["rotated"].as_bool() == Some(true) {
+        region.rotation = Rotation::Clockwise;
+    }
+    if frame["trimmed"].as_bool() == Some(true) {
+        let sprite_source = &frame["spriteSourceSize"];
+        let source = &frame["sourceSize"];
+        region.offset = (number(sprite_source, "x")?, number(sprite_source, "y")?);
+        region.source_size = (number(source, "w")?, number(source, "h")?);
+    }
+    Ok(region)
+}
+
+fn parse_lua(text: &str) -> Result<Atlas, Box<dyn Error>> {
+    let sprite = Regex::new(
+        r"(?m)^    (\S+) = \{\n        x = (\d+),\n        y = (\d+),\n        width = (\d+),\n        height = (\d+),(\n        rotated = true,)?",
+    )?;
+    let regions: Vec<Region> = sprite
+        .captures_iter(text)
+        .map(|c| {
+            let mut region = Region::new(
+                c[1].to_owned(),
+                c[2].parse().unwrap_or_default(),
+                c[3].parse().unwrap_or_default(),
+                c[4].parse().unwrap_or_default(),
+                c[5].parse().unwrap_or_default(),
+            );
+            if c.get(6).is_some() {
+                region.rotation = Rotation::Clockwise;
+            }
+            region
+        })
+        .collect();
+    if regions.is_empty() {
+        return Err("No sprites found in the lua data".into());
+    }
+    // the sheet fields sit in a meta table when the data was written with --meta
+    let meta = Regex::new(r"(?m)^    meta = \{\n((?:        .*\n)*)    \}")?
+        .captures(text)
+        .map(|c| c[1].to_owned())
+        .unwrap_or_default();
+    let sheet_value = |key: &str| -> Option<u32> {
+        Regex::new(&format!(r"(?m)^        {key} = (\d+)"))
+            .ok()?
+            .captures(&meta)?[1]
+            .parse()
+            .ok()
+    };
+    Ok(Atlas {
+        regions,
+        size: sheet_value("width").zip(sheet_value("height")),
+        padding: sheet_value("padding"),
+        premultiplied_alpha: meta.contains("        premultiplied_alpha = true,"),
+    })
+}
+
+fn numbers(value: &str) -> Vec<u32> {
+    value
+        .split(',')
+        .filter_map(|n| n.trim().parse::<i64>().ok())
+        .map(|n| n.max(0) as u32)
+        .collect()
+}
+
+// offsets in libgdx atlases are from the bottom left and are resolved once the
+// region's size is known
+struct LibgdxRegion {
+    region: Region,
+    offset: Option<(u32, u32)>,
+    index: i64,
+}
+
+// both the original libgdx format with xy/size/orig/offset and the newer one with
+// bounds/offsets. libgdx stores rotated sprites turned counter clockwise and measures
+// offsets from the bottom left
+fn parse_libgdx(text: &str) -> Result<Atlas, Box<dyn Error>> {
+    let mut regions: Vec<LibgdxRegion> = vec![];
+    let mut size = None;
+    let mut pages = 0;
+    let mut in_page_header = false;
+    let mut after_blank = true;
+
+    for line in text.lines() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            after_blank = true;
+            continue;
+        }
+        let field = trimmed.split_once(':');
+        match field {
+            None if after_blank => {
+                pages += 1;
+                if pages > 1 {
+                    return Err("Atlases with more than one page are not supported".into());
+                }
+                in_page_header = true;
+            }
+            None => {
+                in_page_header = false;
+                regions.push(LibgdxRegion {
+                    region: Region::new(trimmed.to_owned(), 0, 0, 0, 0),
+                    offset: None,
+                    index: -1,
+                });
+            }
+            Some((key, value)) if in_page_header => {
+                if let ("size", [w, h]) = (key.trim(), numbers(value).as_slice()) {
+                    size = Some((*w, *h));
+                }
+            }
+            Some((key, value)) => {
+                let Some(LibgdxRegion {
+                    region,
+                    offset,
+                    index,
+                }) = regions.last_mut()
+                else {
+                    continue;
+                };
+                let values = numbers(value);
+                match (key.trim(), values.as_slice()) {
+                    ("xy", [x, y]) => (region.x, region.y) = (*x, *y),
+                    ("size", [w, h]) => (region.width, region.height) = (*w, *h),
+                    ("bounds", [x, y, w, h]) => {
+                        (region.x, region.y, region.width, region.height) = (*x, *y, *w, *h)
+                    }
+                    ("orig", [w, h]) => region.source_size = (*w, *h),
+                    ("offset", [x, y]) => *offset = Some((*x, *y)),
+                    ("offsets", [x, y, w, h]) => {
+                        *offset = Some((*x, *y));
+                        region.source_size = (*w, *h);
+                    }
+                    ("rotate", _) => {
+                        if matches!(value.trim(), "true" | "90") {
+                            region.rotation = Rotation::CounterClockwise;
+                        }
+                    }
+                    ("index", _) => *index = value.trim().parse().unwrap_or(-1),
+                    _ => (),
+                }
+            }
+        }
+        after_blank = false;
+    }
+
+    let regions = regions
+        .into_iter()
+        .map(
+            |LibgdxRegion {
+                 mut region,
+                 offset,
+                 index,
+             }| {
+                if region.source_size == (0, 0) {
+                    region.source_size = (region.width, region.height);
+                }
+                if let Some((x, y)) = offset {
+                    let top = region.source_size.1 as i64 - y as i64 - region.height as i64;
+                    region.offset = (x, top.max(0) as u32);
+                }
+                if index >= 0 {
+                    region.name = format!("{}_{index}", region.name);
+                }
+                region
+            },
+        )
+        .collect();
+    Ok(Atlas {
+        regions,
+        size,
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn corral_json() -> Result<(), Box<dyn Error>> {
+        let flat = "[{\"height\":4,\"name\":\"a\",\"width\":3,\"x\":1,\"y\":2}]\n";
+        let object = "{\"meta\":{\"premultiplied_alpha\":true},\"sprites\":[{\"height\":4,\"name\":\"a\",\"width\":3,\"x\":1,\"y\":2}]}\n";
+        for text in [flat, object] {
+            assert_eq!(
+                parse_json(text)?.regions,
+                vec![Region::new("a".to_owned(), 1, 2, 3, 4)]
+            );
+        }
+        assert!(!parse_json(flat)?.premultiplied_alpha);
+        assert!(parse_json(object)?.premultiplied_alpha);
+        let text = "[{\"height\":4,\"name\":\"a\",\"rotated\":true,\"width\":3,\"x\":1,\"y\":2}]";
+        assert_eq!(parse_json(text)?.regions[0].sheet_size(), (4, 3));
+
+        let text = "{\"meta\":{\"height\":16,\"padding\":2,\"width\":8},\"sprites\":[]}";
+        let atlas = parse_json(text)?;
+        assert_eq!(atlas.size, Some((8, 16)));
+        assert_eq!(atlas.padding, Some(2));
+        Ok(())
+    }
+
+    #[test]
+    fn corral_lua() -> Result<(), Box<dyn Error>> {
+        let text = "local out = {\n    RED_PIXEL = {\n        x = 1,\n        y = 2,\n        width = 3,\n        height = 4,\n    },\n    animations = {\n    },\n    meta = {\n        width = 8,\n        height = 16,\n        padding = 2,\n        premultiplied_alpha = true,\n    }\n}\n\nreturn out\n";
+        let atlas = parse_lua(text)?;
+        assert_eq!(
+            atlas.regions,
+            vec![Region::new("RED_PIXEL".to_owned(), 1, 2, 3, 4)]
+        );
+        assert_eq!(atlas.size, Some((8, 16)));
+        assert_eq!(atlas.padding, Some(2));
+        assert!(atlas.premultiplied_alpha);
+
+        let text = "local out = {\n    RED_PIXEL = {\n        x = 1,\n        y = 2,\n        width = 3,\n        height = 4,\n        rotated = true,\n    }\n}\n\nreturn out\n";
+        let atlas = parse_lua(text)?;
+        assert_eq!(atlas.regions[0].rotation, Rotation::Clockwise);
+        assert_eq!(atlas.size, None);
+        Ok(())
+    }
+
+    #[test]
+    fn texture_packer_json() -> Result<(), Box<dyn Error>> {
+        let text = r#"{"frames":{"hero.png":{"frame":{"x":10,"y":20,"w":6,"h":4},"rotated":true,"trimmed":true,"spriteSourceSize":{"x":1,"y":2,"w":6,"h":4},"sourceSize":{"w":8,"h":8}}},"meta":{}}"#;
+        let regions = parse_json(text)?.regions;
+        assert_eq!(regions[0].name, "hero.png");
+        assert_eq!(regions[0].rotation, Rotation::Clockwise);
+        assert_eq!(regions[0].sheet_size(), (4, 6));
+        assert_eq!(regions[0].offset, (1, 2));
+        assert_eq!(regions[0].source_size, (8, 8));
+
+        let text = r#"{"frames":[{"filename":"a","frame":{"x":0,"y":0,"w":2,"h":2},"rotated":false,"trimmed":false}]}"#;
+        let atlas = parse_json(text)?;
+        assert_eq!(atlas.regions, vec![Region::new("a".to_owned(), 0, 0, 2, 2)]);
+        assert_eq!(atlas.size, None);
+        Ok(())
+    }
+
+    #[test]
+    fn libgdx_atlas() -> Result<(), Box<dyn Error>> {
+        let old = "\nsheet.png\nsize: 64,64\nformat: RGBA8888\nfilter: Nearest,Nearest\nrepeat: none\nhero\n  rotate: true\n  xy: 2, 4\n  size: 6, 4\n  orig: 8, 8\n  offset: 1, 2\n  index: 3\n";
+        let new = "sheet.png\nsize:64,64\nfilter:Nearest,Nearest\nhero\nindex:3\nbounds:2,4,6,4\noffsets:1,2,8,8\nrotate:90\n";
+        for text in [old, new] {
+            let atlas = parse_libgdx(text)?;
+            assert_eq!(atlas.size, Some((64, 64)));
+            let regions = atlas.regions;
+            assert_eq!(regions.len(), 1);
+            assert_eq!(regions[0].name, "hero_3");
+            assert_eq!((regions[0].x, regions[0].y), (2, 4));
+            assert_eq!(regions[0].rotation, Rotation::CounterClockwise);
+            assert_eq!(regions[0].source_size, (8, 8));
+            assert_eq!(regions[0].offset, (1, 2));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,638 @@
+use image::RgbaImage;
+
+use crate::config::TextureCompression;
+
+type Block = [[u8; 4]; 16];
+
+const BC7_WEIGHTS: [u32; 16] = [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+
+const ETC_MODIFIERS: [[i32; 4]; 8] = [
+    [2, 8, -2, -8],
+    [5, 17, -5, -17],
+    [9, 29, -9, -29],
+    [13, 42, -13, -42],
+    [18, 60, -18, -60],
+    [24, 80, -24, -80],
+    [33, 106, -33, -106],
+    [47, 183, -47, -183],
+];
+
+const EAC_MODIFIERS: [[i32; 8]; 16] = [
+    [-3, -6, -9, -15, 2, 5, 8, 14],
+    [-3, -7, -10, -13, 2, 6, 9, 12],
+    [-2, -5, -8, -13, 1, 4, 7, 12],
+    [-2, -4, -6, -13, 1, 3, 5, 12],
+    [-3, -6, -8, -12, 2, 5, 7, 11],
+    [-3, -7, -9, -11, 2, 6, 8, 10],
+    [-4, -7, -8, -11, 3, 6, 7, 10],
+    [-3, -5, -8, -11, 2, 4, 7, 10],
+    [-2, -6, -8, -10, 1, 5, 7, 9],
+    [-2, -5, -8, -10, 1, 4, 7, 9],
+    [-2, -4, -8, -10, 1, 3, 7, 9],
+    [-2, -5, -7, -10, 1, 4, 6, 9],
+    [-3, -4, -7, -10, 2, 3, 6, 9],
+    [-1, -2, -3, -10, 0, 1, 2, 9],
+    [-4, -6, -8, -9, 3, 5, 7, 8],
+    [-3, -5, -7, -9, 2, 4, 6, 8],
+];
+
+pub fn compress(img: &RgbaImage, compression: TextureCompression) -> Vec<u8> {
+    if compression == TextureCompression::None {
+        return img.as_raw().clone();
+    }
+
+    let blocks_wide = img.width().div_ceil(4);
+    let blocks_high = img.height().div_ceil(4);
+    let mut result =
+        Vec::with_capacity((blocks_wide * blocks_high) as usize * compression.block_bytes());
+
+    for by in 0..blocks_high {
+        for bx in 0..blocks_wide {
+            let block = block_at(img, bx, by);
+            match compression {
+                TextureCompression::Bc1 => result.extend_from_slice(&encode_bc1(&block, true)),
+                TextureCompression::Bc3 => {
+                    result.extend_from_slice(&encode_bc3_alpha(&block));
+                    result.extend_from_slice(&encode_bc1(&block, false));
+                }
+                TextureCompression::Bc7 => result.extend_from_slice(&encode_bc7(&block)),
+                TextureCompression::Etc2 => {
+                    result.extend_from_slice(&encode_eac_alpha(&block));
+                    result.extend_from_slice(&encode_etc_color(&block));
+                }
+                TextureCompression::None => (),
+            }
+        }
+    }
+
+    result
+}
+
+fn block_at(img: &RgbaImage, bx: u32, by: u32) -> Block {
+    let mut block = [[0u8; 4]; 16];
+    for y in 0..4 {
+        for x in 0..4 {
+            let px = (bx * 4 + x).min(img.width() - 1);
+            let py = (by * 4 + y).min(img.height() - 1);
+            block[(y * 4 + x) as usize] = img.get_pixel(px, py).0;
+        }
+    }
+    block
+}
+
+fn distance<const N: usize>(a: &[f32; N], b: &[f32; N]) -> f32 {
+    a.iter().zip(b.iter()).map(|(a, b)| (a - b) * (a - b)).sum()
+}
+
+fn nearest<const N: usize>(palette: &[[f32; N]], pixel: &[f32; N]) -> usize {
+    let mut best = 0;
+    let mut best_distance = f32::MAX;
+    for (i, colour) in palette.iter().enumerate() {
+        let d = distance(colour, pixel);
+        if d < best_distance {
+            best = i;
+            best_distance = d;
+        }
+    }
+    best
+}
+
+fn endpoints<const N: usize>(pixels: &[[f32; N]]) -> ([f32; N], [f32; N]) {
+    let mut mean = [0f32; N];
+    for pixel in pixels {
+        for c in 0..N {
+            mean[c] += pixel[c] / pixels.len() as f32;
+        }
+    }
+
+    let mut covariance = [[0f32; N]; N];
+    for pixel in pixels {
+        for i in 0..N {
+            for j in 0..N {
+                covariance[i][j] += (pixel[i] - mean[i]) * (pixel[j] - mean[j]);
+            }
+        }
+    }
+
+    let mut largest = 0;
+    for c in 0..N {
+        if covariance[c][c] > covariance[largest][largest] {
+            largest = c;
+        }
+    }
+    let mut axis = covariance[largest];
+    for _ in 0..8 {
+        let mut next = [0f32; N];
+        for i in 0..N {
+            for j in 0..N {
+                next[i] += covariance[i][j] * axis[j];
+            }
+        }
+        let norm = next.iter().map(|v| v * v).sum::<f32>().sqrt();
+        if norm < f32::EPSILON {
+            return (mean, mean);
+        }
+        axis = next.map(|v| v / norm);
+    }
+
+    let mut t_min = f32::MAX;
+    let mut t_max = f32::MIN;
+    for pixel in pixels {
+        let t: f32 = (0..N).map(|c| (pixel[c] - mean[c]) * axis[c]).sum();
+        t_min = t_min.min(t);
+        t_max = t_max.max(t);
+    }
+
+    let mut start = [0f32; N];
+    let mut end = [0f32; N];
+    for c in 0..N {
+        start[c] = (mean[c] + axis[c] * t_min).clamp(0.0, 255.0);
+        end[c] = (mean[c] + axis[c] * t_max).clamp(0.0, 255.0);
+    }
+    (start, end)
+}
+
+fn to_565(colour: &[f32; 3]) -> u16 {
+    let r = (colour[0] * 31.0 / 255.0).round() as u16;
+    let g = (colour[1] * 63.0 / 255.0).round() as u16;
+    let b = (colour[2] * 31.0 / 255.0).round() as u16;
+    (r << 11) | (g << 5) | b
+}
+
+fn from_565(colour: u16) -> [f32; 3] {
+    let r = (colour >> 11) & 31;
+    let g = (colour >> 5) & 63;
+    let b = colour & 31;
+    [
+        ((r << 3) | (r >> 2)) as f32,
+        ((g << 2) | (g >> 4)) as f32,
+        ((b << 3) | (b >> 2)) as f32,
+    ]
+}
+
+fn lerp<const N: usize>(a: &[f32; N], b: &[f32; N], numerator: f32, denominator: f32) -> [f32; N] {
+    let mut result = [0f32; N];
+    for c in 0..N {
+        result[c] = ((a[c] * (denominator - numerator) + b[c] * numerator) / denominator).floor();
+    }
+    result
+}
+
+fn encode_bc1(block: &Block, allow_transparent: bool) -> [u8; 8] {
+    let transparent = block.map(|pixel| allow_transparent && pixel[3] < 128);
+    let opaque: Vec<[f32; 3]> = block
+        .iter()
+        .zip(transparent.iter())
+        .filter(|(_, transparent)| !**transparent)
+        .map(|(pixel, _)| [pixel[0] as f32, pixel[1] as f32, pixel[2] as f32])
+        .collect();
+
+    if opaque.is_empty() {
+        return [0, 0, 0, 0, 0xff, 0xff, 0xff, 0xff];
+    }
+
+    let (start, end) = endpoints(&opaque);
+    let (a, b) = (to_565(&start), to_565(&end));
+    let has_transparent = transparent.iter().any(|t| *t);
+
+    // colour_0 <= colour_1 selects the three colour mode with a transparent index
+    let (c0, c1) = if has_transparent {
+        (a.min(b), a.max(b))
+    } else {
+        (a.max(b), a.min(b))
+    };
+    let (e0, e1) = (from_565(c0), from_565(c1));
+    let palette = if c0 > c1 {
+        vec![e0, e1, lerp(&e0, &e1, 1.0, 3.0), lerp(&e0, &e1, 2.0, 3.0)]
+    } else {
+        vec![e0, e1, lerp(&e0, &e1, 1.0, 2.0)]
+    };
+
+    let mut indices = 0u32;
+    for (i, pixel) in block.iter().enumerate() {
+        let index = if transparent[i] {
+            3
+        } else if c0 == c1 {
+            0
+        } else {
+            nearest(
+                &palette,
+                &[pixel[0] as f32, pixel[1] as f32, pixel[2] as f32],
+            )
+        };
+        indices |= (index as u32) << (2 * i);
+    }
+
+    let mut result = [0u8; 8];
+    result[0..2].copy_from_slice(&c0.to_le_bytes());
+    result[2..4].copy_from_slice(&c1.to_le_bytes());
+    result[4..8].copy_from_slice(&indices.to_le_bytes());
+    result
+}
+
+fn encode_bc3_alpha(block: &Block) -> [u8; 8] {
+    let a0 = block.iter().map(|pixel| pixel[3]).max().unwrap_or(0);
+    let a1 = block.iter().map(|pixel| pixel[3]).min().unwrap_or(0);
+
+    let mut result = [0u8; 8];
+    result[0] = a0;
+    result[1] = a1;
+    if a0 == a1 {
+        return result;
+    }
+
+    let mut palette = vec![[a0 as f32], [a1 as f32]];
+    for k in 1..7 {
+        palette.push([((7 - k) * a0 as u32 + k * a1 as u32) as f32 / 7.0]);
+    }
+
+    let mut indices = 0u64;
+    for (i, pixel) in block.iter().enumerate() {
+        indices |= (nearest(&palette, &[pixel[3] as f32]) as u64) << (3 * i);
+    }
+    result[2..8].copy_from_slice(&indices.to_le_bytes()[0..6]);
+    result
+}
+
+fn quantize_bc7_endpoint(endpoint: &[f32; 4]) -> ([u8; 4], u8) {
+    let mut best = ([0u8; 4], 0u8);
+    let mut best_error = f32::MAX;
+    for p_bit in 0..2u8 {
+        let quantized =
+            endpoint.map(|v| ((v - p_bit as f32) / 2.0).round().clamp(0.0, 127.0) as u8);
+        let error: f32 = (0..4)
+            .map(|c| {
+                let v = ((quantized[c] << 1) | p_bit) as f32;
+                (v - endpoint[c]) * (v - endpoint[c])
+            })
+            .sum();
+        if error < best_error {
+            best = (quantized, p_bit);
+            best_error = error;
+        }
+    }
+    best
+}
+
+struct BitWriter {
+    value: u128,
+    offset: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u32, bits: u32) {
+        self.value |= (value as u128 & ((1u128 << bits) - 1)) << self.offset;
+        self.offset += bits;
+    }
+}
+
+// Mode 6 only: a single RGBA subset with 7 bit endpoints, p-bits and 4 bit indices
+fn encode_bc7(block: &Block) -> [u8; 16] {
+    let pixels = block.map(|pixel| pixel.map(|c| c as f32));
+    let (start, end) = endpoints(&pixels);
+    let (mut q0, mut p0) = quantize_bc7_endpoint(&start);
+    let (mut q1, mut p1) = quantize_bc7_endpoint(&end);
+
+    let e0 = q0.map(|c| ((c << 1) | p0) as u32);
+    let e1 = q1.map(|c| ((c << 1) | p1) as u32);
+    let palette: Vec<[f32; 4]> = BC7_WEIGHTS
+        .iter()
+        .map(|w| {
+            let mut colour = [0f32; 4];
+            for c in 0..4 {
+                colour[c] = (((64 - w) * e0[c] + w * e1[c] + 32) >> 6) as f32;
+            }
+            colour
+        })
+        .collect();
+
+    let mut indices = pixels.map(|pixel| nearest(&palette, &pixel) as u32);
+    if indices[0] >= 8 {
+        std::mem::swap(&mut q0, &mut q1);
+        std::mem::swap(&mut p0, &mut p1);
+        indices = indices.map(|i| 15 - i);
+    }
+
+    let mut writer = BitWriter {
+        value: 0,
+        offset: 0,
+    };
+    writer.write(1 << 6, 7);
+    for c in 0..4 {
+        writer.write(q0[c] as u32, 7);
+        writer.write(q1[c] as u32, 7);
+    }
+    writer.write(p0 as u32, 1);
+    writer.write(p1 as u32, 1);
+    for (i, index) in indices.iter().enumerate() {
+        writer.write(*index, if i == 0 { 3 } else { 4 });
+    }
+    writer.value.to_le_bytes()
+}
+
+fn etc_pixel_bit(i: usize) -> usize {
+    // etc blocks number their pixels column by column
+    (i % 4) * 4 + i / 4
+}
+
+fn etc_subblock(flip: bool, subblock: usize) -> Vec<usize> {
+    (0..16)
+        .filter(|i| {
+            let position = if flip { i / 4 } else { i % 4 };
+            (position >= 2) == (subblock == 1)
+        })
+        .collect()
+}
+
+fn etc_fit_table(block: &Block, pixels: &[usize], base: [i32; 3]) -> (u32, f32, Vec<(usize, u32)>) {
+    let mut best = (0, f32::MAX, vec![]);
+    for (table, modifiers) in ETC_MODIFIERS.iter().enumerate() {
+        let mut error = 0f32;
+        let mut indices = vec![];
+        for i in pixels {
+            let pixel = block[*i];
+            let weight = if pixel[3] == 0 { 0.0 } else { 1.0 };
+            let mut best_index = 0;
+            let mut best_error = f32::MAX;
+            for (index, modifier) in modifiers.iter().enumerate() {
+                let e: f32 = (0..3)
+                    .map(|c| {
+                        let v = (base[c] + modifier).clamp(0, 255) - pixel[c] as i32;
+                        (v * v) as f32
+                    })
+                    .sum();
+                if e < best_error {
+                    best_index = index as u32;
+                    best_error = e;
+                }
+            }
+            error += best_error * weight;
+            indices.push((*i, best_index));
+        }
+        if error < best.1 {
+            best = (table as u32, error, indices);
+        }
+    }
+    best
+}
+
+fn encode_etc_color(block: &Block) -> [u8; 8] {
+    let mut best_word = 0u64;
+    let mut best_error = f32::MAX;
+
+    for flip in [false, true] {
+        let subblocks = [etc_subblock(flip, 0), etc_subblock(flip, 1)];
+        let averages = subblocks.clone().map(|pixels| {
+            let mut sum = [0f32; 3];
+            let mut count = 0f32;
+            for i in &pixels {
+                if block[*i][3] > 0 {
+                    for c in 0..3 {
+                        sum[c] += block[*i][c] as f32;
+                    }
+                    count += 1.0;
+                }
+            }
+            sum.map(|v| if count > 0.0 { v / count } else { 0.0 })
+        });
+
+        let individual = averages.map(|avg| avg.map(|v| (v * 15.0 / 255.0).round() as i32));
+        let differential = averages.map(|avg| avg.map(|v| (v * 31.0 / 255.0).round() as i32));
+        let deltas = [0, 1, 2].map(|c| differential[1][c] - differential[0][c]);
+
+        let mut candidates = vec![(
+            false,
+            individual.map(|q| q.map(|v| (v << 4) | v)),
+            individual,
+        )];
+        if deltas.iter().all(|d| (-4..=3).contains(d)) {
+            candidates.push((
+                true,
+                differential.map(|q| q.map(|v| (v << 3) | (v >> 2))),
+                differential,
+            ));
+        }
+
+        for (diff, bases, quantized) in candidates {
+            let (table0, error0, indices0) = etc_fit_table(block, &subblocks[0], bases[0]);
+            let (table1, error1, indices1) = etc_fit_table(block, &subblocks[1], bases[1]);
+            if error0 + error1 >= best_error {
+                continue;
+            }
+            best_error = error0 + error1;
+
+            let mut word = 0u64;
+            if diff {
+                for (c, shift) in [59, 51, 43].iter().enumerate() {
+                    let delta = (quantized[1][c] - quantized[0][c]) & 7;
+                    word |= (quantized[0][c] as u64) << shift;
+                    word |= (delta as u64) << (shift - 3);
+                }
+            } else {
+                for (c, shift) in [60, 52, 44].iter().enumerate() {
+                    word |= (quantized[0][c] as u64) << shift;
+                    word |= (quantized[1][c] as u64) << (shift - 4);
+                }
+            }
+            word |= (table0 as u64) << 37;
+            word |= (table1 as u64) << 34;
+            word |= (diff as u64) << 33;
+            word |= (flip as u64) << 32;
+            for (i, index) in indices0.iter().chain(indices1.iter()) {
+                let bit = etc_pixel_bit(*i);
+                word |= ((*index as u64) >> 1) << (16 + bit);
+                word |= ((*index as u64) & 1) << bit;
+            }
+            best_word = word;
+        }
+    }
+
+    best_word.to_be_bytes()
+}
+
+fn encode_eac_alpha(block: &Block) -> [u8; 8] {
+    let alphas = block.map(|pixel| pixel[3] as i32);
+    let a_min = *alphas.iter().min().unwrap_or(&0);
+    let a_max = *alphas.iter().max().unwrap_or(&0);
+
+    let mut best_word = 0u64;
+    let mut best_error = i32::MAX;
+
+    for (table, modifiers) in EAC_MODIFIERS.iter().enumerate() {
+        let t_min = *modifiers.iter().min().unwrap_or(&0);
+        let t_max = *modifiers.iter().max().unwrap_or(&0);
+        let ideal = ((a_max - a_min) as f32 / (t_max - t_min) as f32).round() as i32;
+        for multiplier in (ideal - 1).max(1)..=(ideal + 1).clamp(1, 15) {
+            let midpoint = (a_min + a_max) / 2 - (t_min + t_max) * multiplier / 2;
+            for base in [a_min - t_min * multiplier, midpoint] {
+                let base = base.clamp(0, 255);
+                let mut error = 0;
+                let mut word =
+                    ((base as u64) << 56) | ((multiplier as u64) << 52) | ((table as u64) << 48);
+                for (i, alpha) in alphas.iter().enumerate() {
+                    let mut best_index = 0;
+                    let mut best_pixel_error = i32::MAX;
+                    for (index, modifier) in modifiers.iter().enumerate() {
+                        let e = ((base + modifier * multiplier).clamp(0, 255) - alpha).abs();
+                        if e < best_pixel_error {
+                            best_index = index;
+                            best_pixel_error = e;
+                        }
+                    }
+                    error += best_pixel_error * best_pixel_error;
+                    word |= (best_index as u64) << (45 - 3 * etc_pixel_bit(i));
+                }
+                if error < best_error {
+                    best_error = error;
+                    best_word = word;
+                }
+            }
+        }
+    }
+
+    best_word.to_be_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradient_block() -> Block {
+        let mut block = [[0u8; 4]; 16];
+        for (i, pixel) in block.iter_mut().enumerate() {
+            let v = (i * 16) as u8;
+            *pixel = [v, 255 - v, 128, 255];
+        }
+        block
+    }
+
+    fn decode_bc7_mode6(bytes: &[u8; 16]) -> Block {
+        let value = u128::from_le_bytes(*bytes);
+        let read = |offset: u32, bits: u32| ((value >> offset) & ((1 << bits) - 1)) as u32;
+        assert_eq!(read(0, 7), 1 << 6);
+        let (p0, p1) = (read(63, 1), read(64, 1));
+        let mut e0 = [0u32; 4];
+        let mut e1 = [0u32; 4];
+        for c in 0..4 {
+            e0[c] = (read(7 + 14 * c as u32, 7) << 1) | p0;
+            e1[c] = (read(14 + 14 * c as u32, 7) << 1) | p1;
+        }
+        let mut block = [[0u8; 4]; 16];
+        let mut offset = 65;
+        for (i, pixel) in block.iter_mut().enumerate() {
+            let bits = if i == 0 { 3 } else { 4 };
+            let w = BC7_WEIGHTS[read(offset, bits) as usize];
+            offset += bits;
+            for c in 0..4 {
+                pixel[c] = (((64 - w) * e0[c] + w * e1[c] + 32) >> 6) as u8;
+            }
+        }
+        block
+    }
+
+    fn decode_etc2_rgba(bytes: &[u8]) -> Block {
+        let alpha = u64::from_be_bytes(bytes[0..8].try_into().unwrap());
+        let color = u64::from_be_bytes(bytes[8..16].try_into().unwrap());
+        let diff = (color >> 33) & 1 == 1;
+        let flip = (color >> 32) & 1 == 1;
+        let mut bases = [[0i32; 3]; 2];
+        for (c, shift) in [0, 8, 16].iter().enumerate() {
+            if diff {
+                let shift = 59 - shift;
+                let q0 = ((color >> shift) & 31) as i32;
+                let delta = (((color >> (shift - 3)) & 7) as i32 ^ 4) - 4;
+                let q1 = q0 + delta;
+                bases[0][c] = (q0 << 3) | (q0 >> 2);
+                bases[1][c] = (q1 << 3) | (q1 >> 2);
+            } else {
+                let shift = 60 - shift;
+                let q0 = ((color >> shift) & 15) as i32;
+                let q1 = ((color >> (shift - 4)) & 15) as i32;
+                bases[0][c] = (q0 << 4) | q0;
+                bases[1][c] = (q1 << 4) | q1;
+            }
+        }
+        let tables = [((color >> 37) & 7) as usize, ((color >> 34) & 7) as usize];
+        let base = (alpha >> 56) as i32;
+        let multiplier = ((alpha >> 52) & 15) as i32;
+        let alpha_table = ((alpha >> 48) & 15) as usize;
+
+        let mut block = [[0u8; 4]; 16];
+        for (i, pixel) in block.iter_mut().enumerate() {
+            let (x, y) = (i % 4, i / 4);
+            let subblock = if flip { y / 2 } else { x / 2 };
+            let bit = etc_pixel_bit(i);
+            let index = (((color >> (16 + bit)) & 1) << 1 | ((color >> bit) & 1)) as usize;
+            let modifier = ETC_MODIFIERS[tables[subblock]][index];
+            for c in 0..3 {
+                pixel[c] = (bases[subblock][c] + modifier).clamp(0, 255) as u8;
+            }
+            let alpha_index = ((alpha >> (45 - 3 * bit)) & 7) as usize;
+            pixel[3] =
+                (base + EAC_MODIFIERS[alpha_table][alpha_index] * multiplier).clamp(0, 255) as u8;
+        }
+        block
+    }
+
+    fn max_error(a: &Block, b: &Block) -> i32 {
+        a.iter()
+            .zip(b.iter())
+            .flat_map(|(p, q)| (0..4).map(move |c| (p[c] as i32 - q[c] as i32).abs()))
+            .max()
+            .unwrap_or(0)
+    }
+
+    #[test]
+    fn bc1_solid_block() {
+        let block = [[255, 0, 0, 255]; 16];
+        let encoded = encode_bc1(&block, true);
+        assert_eq!(u16::from_le_bytes([encoded[0], encoded[1]]), 0xf800);
+        assert_eq!(u32::from_le_bytes(encoded[4..8].try_into().unwrap()), 0);
+    }
+
+    #[test]
+    fn bc1_transparent_pixels_use_index_three() {
+        let mut block = [[0, 0, 255, 255]; 16];
+        block[5] = [0, 0, 0, 0];
+        let encoded = encode_bc1(&block, true);
+        let c0 = u16::from_le_bytes([encoded[0], encoded[1]]);
+        let c1 = u16::from_le_bytes([encoded[2], encoded[3]]);
+        let indices = u32::from_le_bytes(encoded[4..8].try_into().unwrap());
+        assert!(c0 <= c1);
+        assert_eq!((indices >> 10) & 3, 3);
+    }
+
+    #[test]
+    fn bc7_round_trip() {
+        let block = gradient_block();
+        let decoded = decode_bc7_mode6(&encode_bc7(&block));
+        assert!(max_error(&block, &decoded) <= 8);
+    }
+
+    #[test]
+    fn etc2_round_trip() {
+        let mut block = [[200, 40, 40, 255]; 16];
+        for pixel in block.iter_mut().skip(8) {
+            *pixel = [40, 40, 200, 0];
+        }
+        let mut encoded = encode_eac_alpha(&block).to_vec();
+        encoded.extend_from_slice(&encode_etc_color(&block));
+        let decoded = decode_etc2_rgba(&encoded);
+        for (p, q) in block.iter().zip(decoded.iter()) {
+            assert_eq!(p[3], q[3]);
+            if p[3] > 0 {
+                for c in 0..3 {
+                    assert!((p[c] as i32 - q[c] as i32).abs() <= 12);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn compressed_size() {
+        let img = RgbaImage::new(10, 6);
+        assert_eq!(compress(&img, TextureCompression::Bc1).len(), 3 * 2 * 8);
+        assert_eq!(compress(&img, TextureCompression::Bc7).len(), 3 * 2 * 16);
+        assert_eq!(compress(&img, TextureCompression::None).len(), 10 * 6 * 4);
+    }
+}
@@ -58,6 +58,7 @@ impl PartialOrd for BoundingBox {
 }
 
 #[cfg(test)]
+#[allow(clippy::op_ref, clippy::bool_assert_comparison)]
 mod tests {
     use super::*;
 
@@ -1,4 +1,12 @@
-use std::{fmt, process::exit};
+use std::{fmt, path::Path, process::exit, time::Duration};
+
+use image::imageops::FilterType;
+use regex::Regex;
+
+use crate::animation::DEFAULT_FRAME_PATTERN;
+use crate::group::Grouping;
+use crate::optimize;
+use crate::tree2d::LeafChoice;
 
 #[derive(Clone, Copy)]
 pub enum MetaDataFormat {
@@ -6,11 +14,148 @@ pub enum MetaDataFormat {
     Lua,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageFormat {
+    Png,
+    Ktx2,
+    Dds,
+    Exr,
+}
+
+impl ImageFormat {
+    fn from_file_name(file_name: &str) -> ImageFormat {
+        let extension = Path::new(file_name)
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase());
+        match extension.as_deref() {
+            Some("ktx2") => ImageFormat::Ktx2,
+            Some("dds") => ImageFormat::Dds,
+            Some("exr") => ImageFormat::Exr,
+            _ => ImageFormat::Png,
+        }
+    }
+
+    fn is_gpu_texture(&self) -> bool {
+        matches!(self, ImageFormat::Ktx2 | ImageFormat::Dds)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextureCompression {
+    None,
+    Bc1,
+    Bc3,
+    Bc7,
+    Etc2,
+}
+
+impl TextureCompression {
+    pub fn block_dimension(&self) -> u32 {
+        match self {
+            TextureCompression::None => 1,
+            _ => 4,
+        }
+    }
+
+    pub fn block_bytes(&self) -> usize {
+        match self {
+            TextureCompression::None => 4,
+            TextureCompression::Bc1 => 8,
+            _ => 16,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SortKey {
+    Area,
+    MaxSide,
+    Perimeter,
+    Height,
+    Width,
+    None,
+}
+
+impl SortKey {
+    pub const ALL: [SortKey; 6] = [
+        SortKey::Area,
+        SortKey::MaxSide,
+        SortKey::Perimeter,
+        SortKey::Height,
+        SortKey::Width,
+        SortKey::None,
+    ];
+
+    pub fn key(&self, width: u32, height: u32) -> u64 {
+        let (width, height) = (width as u64, height as u64);
+        match self {
+            SortKey::Area => width * height,
+            SortKey::MaxSide => width.max(height),
+            SortKey::Perimeter => 2 * (width + height),
+            SortKey::Height => height,
+            SortKey::Width => width,
+            SortKey::None => 0,
+        }
+    }
+}
+
+impl fmt::Display for SortKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self {
+            SortKey::Area => write!(f, "area"),
+            SortKey::MaxSide => write!(f, "max-side"),
+            SortKey::Perimeter => write!(f, "perimeter"),
+            SortKey::Height => write!(f, "height"),
+            SortKey::Width => write!(f, "width"),
+            SortKey::None => write!(f, "none"),
+        }
+    }
+}
+
+const MAX_SCALE_DENOMINATOR: u32 = 64;
+
+// the smallest whole number that `scale` takes to a whole number, sheets are
+// packed on a grid of it so every position lands on a whole pixel
+pub fn scale_denominator(scale: f32) -> Option<u32> {
+    (1..=MAX_SCALE_DENOMINATOR).find(|denominator| {
+        let scaled = scale as f64 * *denominator as f64;
+        (scaled - scaled.round()).abs() < 1e-4
+    })
+}
+
 pub struct Config {
     pub padding: u8,
     pub input_dir: String,
     pub output_file: String,
     pub output_file_format: MetaDataFormat,
+    pub meta: bool,
+    pub image_format: ImageFormat,
+    pub compression: TextureCompression,
+    pub mipmaps: bool,
+    pub indexed: bool,
+    pub dither: bool,
+    pub palette_file: Option<String>,
+    pub preserve_depth: bool,
+    pub premultiply_alpha: bool,
+    pub alpha_bleed: bool,
+    pub scales: Vec<f32>,
+    pub scale_suffixes: Vec<String>,
+    pub filter: FilterType,
+    pub frame_pattern: Regex,
+    pub split_layers: bool,
+    pub grid_names: bool,
+    pub report_file: Option<String>,
+    pub debug_image: Option<String>,
+    pub preview_html: bool,
+    pub sort_key: SortKey,
+    pub leaf_choice: LeafChoice,
+    pub best: bool,
+    pub jobs: Option<usize>,
+    pub optimize: Option<optimize::Settings>,
+    pub stable_file: Option<String>,
+    pub sheet_size: Option<(u32, u32)>,
+    pub allow_partial: bool,
+    pub grouping: Option<Grouping>,
 }
 
 struct NamedArg<'a> {
@@ -42,13 +187,19 @@ struct NamedParam<'a> {
 }
 
 impl<'a> NamedParam<'a> {
-    fn parse(&self, args: &[String]) -> Result<Option<NamedArg>, &'a str> {
+    fn parse<'b>(&self, args: &'b [String]) -> Result<Option<NamedArg<'b>>, String>
+    where
+        'a: 'b,
+    {
         for arg in args {
             if let Some(index) = arg.find(&format!("--{name}", name = self.name)) {
                 if index != 0 {
                     return Ok(None);
                 }
-                let invoked_arg: Vec<&str> = arg.split('=').collect();
+                let invoked_arg: Vec<&str> = arg.splitn(2, '=').collect();
+                if invoked_arg[0] != format!("--{name}", name = self.name) {
+                    continue;
+                }
                 if invoked_arg.len() == 1 {
                     return Ok(Some(NamedArg {
                         name: self.name,
@@ -56,7 +207,7 @@ impl<'a> NamedParam<'a> {
                     }));
                 }
                 if invoked_arg.len() != 2 {
-                    return Err("incorrect format");
+                    return Err("incorrect format".into());
                 }
                 match self.valid_values {
                     Some(valid_values) => {
@@ -68,11 +219,17 @@ impl<'a> NamedParam<'a> {
                                 }));
                             }
                         }
+                        return Err(format!(
+                            "Invalid --{name}={value}, expected one of {values}",
+                            name = self.name,
+                            value = invoked_arg[1],
+                            values = valid_values.join("|")
+                        ));
                     }
                     None => {
                         return Ok(Some(NamedArg {
                             name: self.name,
-                            value: None,
+                            value: Some(invoked_arg[1]),
                         }));
                     }
                 }
@@ -101,12 +258,140 @@ impl fmt::Display for NamedParam<'_> {
 }
 
 impl Config {
-    pub fn parse(args: &[String]) -> Result<Config, &'static str> {
+    pub fn parse(args: &[String]) -> Result<Config, String> {
         let named_params = [
             NamedParam {
                 name: "data-fmt",
                 valid_values: Some(&["json", "lua"]),
             },
+            NamedParam {
+                name: "meta",
+                valid_values: None,
+            },
+            NamedParam {
+                name: "compression",
+                valid_values: Some(&["none", "bc1", "bc3", "bc7", "etc2"]),
+            },
+            NamedParam {
+                name: "mipmaps",
+                valid_values: None,
+            },
+            NamedParam {
+                name: "indexed",
+                valid_values: None,
+            },
+            NamedParam {
+                name: "dither",
+                valid_values: None,
+            },
+            NamedParam {
+                name: "palette",
+                valid_values: None,
+            },
+            NamedParam {
+                name: "preserve-depth",
+                valid_values: None,
+            },
+            NamedParam {
+                name: "premultiply-alpha",
+                valid_values: None,
+            },
+            NamedParam {
+                name: "alpha-bleed",
+                valid_values: None,
+            },
+            NamedParam {
+                name: "scales",
+                valid_values: None,
+            },
+            NamedParam {
+                name: "scale-suffixes",
+                valid_values: None,
+            },
+            NamedParam {
+                name: "filter",
+                valid_values: Some(&["nearest", "triangle", "catmull-rom", "gaussian", "lanczos"]),
+            },
+            NamedParam {
+                name: "frame-pattern",
+                valid_values: None,
+            },
+            NamedParam {
+                name: "split-layers",
+                valid_values: None,
+            },
+            NamedParam {
+                name: "grid-names",
+                valid_values: None,
+            },
+            NamedParam {
+                name: "report",
+                valid_values: None,
+            },
+            NamedParam {
+                name: "debug-image",
+                valid_values: None,
+            },
+            NamedParam {
+                name: "preview-html",
+                valid_values: None,
+            },
+            NamedParam {
+                name: "sort",
+                valid_values: Some(&["area", "max-side", "perimeter", "height", "width", "none"]),
+            },
+            NamedParam {
+                name: "leaf",
+                valid_values: Some(&["most-square", "smallest-box", "best-short-side"]),
+            },
+            NamedParam {
+                name: "best",
+                valid_values: None,
+            },
+            NamedParam {
+                name: "jobs",
+                valid_values: None,
+            },
+            NamedParam {
+                name: "optimize",
+                valid_values: None,
+            },
+            NamedParam {
+                name: "seed",
+                valid_values: None,
+            },
+            NamedParam {
+                name: "time-budget",
+                valid_values: None,
+            },
+            NamedParam {
+                name: "iterations",
+                valid_values: None,
+            },
+            NamedParam {
+                name: "target-occupancy",
+                valid_values: None,
+            },
+            NamedParam {
+                name: "allow-rotation",
+                valid_values: None,
+            },
+            NamedParam {
+                name: "stable",
+                valid_values: None,
+            },
+            NamedParam {
+                name: "size",
+                valid_values: None,
+            },
+            NamedParam {
+                name: "allow-partial",
+                valid_values: None,
+            },
+            NamedParam {
+                name: "groups",
+                valid_values: None,
+            },
             NamedParam {
                 name: "help",
                 valid_values: None,
@@ -114,9 +399,41 @@ impl Config {
         ];
 
         let mut metadata_format = MetaDataFormat::Json;
+        let mut compression = TextureCompression::None;
+        let mut meta = false;
+        let mut mipmaps = false;
+        let mut indexed = false;
+        let mut dither = false;
+        let mut palette_file = None;
+        let mut preserve_depth = false;
+        let mut premultiply_alpha = false;
+        let mut alpha_bleed = false;
+        let mut scales = vec![1f32];
+        let mut scale_suffixes = None;
+        let mut filter = FilterType::Lanczos3;
+        let mut frame_pattern = DEFAULT_FRAME_PATTERN;
+        let mut split_layers = false;
+        let mut grid_names = false;
+        let mut report_file = None;
+        let mut debug_image = None;
+        let mut preview_html = false;
+        let mut sort_key = SortKey::Area;
+        let mut leaf_choice = LeafChoice::MostSquare;
+        let mut best = false;
+        let mut jobs = None;
+        let mut optimize = false;
+        let mut allow_rotation = false;
+        let mut seed = None;
+        let mut time_budget = None;
+        let mut iterations = None;
+        let mut target_occupancy = None;
+        let mut stable_file = None;
+        let mut sheet_size = None;
+        let mut allow_partial = false;
+        let mut grouping = None;
 
         for named_param in &named_params {
-            if let Ok(Some(arg)) = named_param.parse(args) {
+            if let Some(arg) = named_param.parse(args)? {
                 match arg {
                     NamedArg {
                         name: "data-fmt",
@@ -126,32 +443,420 @@ impl Config {
                         name: "data-fmt",
                         value: Some("lua"),
                     } => metadata_format = MetaDataFormat::Lua,
+                    NamedArg {
+                        name: "compression",
+                        value: Some(value),
+                    } => {
+                        compression = match value {
+                            "bc1" => TextureCompression::Bc1,
+                            "bc3" => TextureCompression::Bc3,
+                            "bc7" => TextureCompression::Bc7,
+                            "etc2" => TextureCompression::Etc2,
+                            _ => TextureCompression::None,
+                        }
+                    }
+                    NamedArg {
+                        name: "mipmaps",
+                        value: None,
+                    } => mipmaps = true,
+                    NamedArg {
+                        name: "indexed",
+                        value: None,
+                    } => indexed = true,
+                    NamedArg {
+                        name: "dither",
+                        value: None,
+                    } => dither = true,
+                    NamedArg {
+                        name: "palette",
+                        value: Some(value),
+                    } => {
+                        indexed = true;
+                        palette_file = Some(value.to_owned());
+                    }
+                    NamedArg {
+                        name: "meta",
+                        value: None,
+                    } => meta = true,
+                    NamedArg {
+                        name: "preserve-depth",
+                        value: None,
+                    } => preserve_depth = true,
+                    NamedArg {
+                        name: "premultiply-alpha",
+                        value: None,
+                    } => premultiply_alpha = true,
+                    NamedArg {
+                        name: "alpha-bleed",
+                        value: None,
+                    } => alpha_bleed = true,
+                    NamedArg {
+                        name: "scales",
+                        value: Some(value),
+                    } => {
+                        scales = value
+                            .split(',')
+                            .map(|scale| scale.trim().parse::<f32>())
+                            .collect::<Result<Vec<f32>, _>>()
+                            .ok()
+                            .filter(|scales| scales.iter().all(|scale| *scale > 0.0))
+                            .ok_or("Invalid --scales, expected a list of positive numbers like --scales=1,0.5")?;
+                        if scales
+                            .iter()
+                            .any(|scale| scale_denominator(*scale).is_none())
+                        {
+                            return Err("Every scale needs to be a fraction with a denominator of at most 64, like 0.75 or 0.4, so sprites land on whole pixels".into());
+                        }
+                    }
+                    NamedArg {
+                        name: "scale-suffixes",
+                        value: Some(value),
+                    } => scale_suffixes = Some(value.split(',').map(|s| s.to_owned()).collect()),
+                    NamedArg {
+                        name: "filter",
+                        value: Some(value),
+                    } => {
+                        filter = match value {
+                            "nearest" => FilterType::Nearest,
+                            "triangle" => FilterType::Triangle,
+                            "catmull-rom" => FilterType::CatmullRom,
+                            "gaussian" => FilterType::Gaussian,
+                            _ => FilterType::Lanczos3,
+                        }
+                    }
+                    NamedArg {
+                        name: "frame-pattern",
+                        value: Some(value),
+                    } => frame_pattern = value,
+                    NamedArg {
+                        name: "split-layers",
+                        value: None,
+                    } => split_layers = true,
+                    NamedArg {
+                        name: "grid-names",
+                        value: None,
+                    } => grid_names = true,
+                    NamedArg {
+                        name: "report",
+                        value: Some(value),
+                    } => report_file = Some(value.to_owned()),
+                    NamedArg {
+                        name: "debug-image",
+                        value: Some(value),
+                    } => debug_image = Some(value.to_owned()),
+                    NamedArg {
+                        name: "preview-html",
+                        value: None,
+                    } => preview_html = true,
+                    NamedArg {
+                        name: "stable",
+                        value: Some(value),
+                    } => stable_file = Some(value.to_owned()),
+                    NamedArg {
+                        name: "size",
+                        value: Some(value),
+                    } => {
+                        sheet_size = Some(
+                            value
+                                .split_once('x')
+                                .and_then(|(width, height)| {
+                                    Some((width.parse::<u32>().ok()?, height.parse::<u32>().ok()?))
+                                })
+                                .filter(|(width, height)| *width > 0 && *height > 0)
+                                .ok_or("Invalid --size, expected WIDTHxHEIGHT such as 2048x2048")?,
+                        )
+                    }
+                    NamedArg {
+                        name: "allow-partial",
+                        value: None,
+                    } => allow_partial = true,
+                    NamedArg {
+                        name: "groups",
+                        value: None,
+                    } => grouping = Some(Grouping::Directories),
+                    NamedArg {
+                        name: "groups",
+                        value: Some(value),
+                    } => grouping = Some(Grouping::Rules(value.to_owned())),
+                    NamedArg {
+                        name: "sort",
+                        value: Some(value),
+                    } => {
+                        sort_key = SortKey::ALL
+                            .into_iter()
+                            .find(|sort_key| sort_key.to_string() == value)
+                            .unwrap_or(SortKey::Area)
+                    }
+                    NamedArg {
+                        name: "leaf",
+                        value: Some(value),
+                    } => {
+                        leaf_choice = LeafChoice::ALL
+                            .into_iter()
+                            .find(|leaf_choice| leaf_choice.to_string() == value)
+                            .unwrap_or(LeafChoice::MostSquare)
+                    }
+                    NamedArg {
+                        name: "best",
+                        value: None,
+                    } => best = true,
+                    NamedArg {
+                        name: "jobs",
+                        value: Some(value),
+                    } => {
+                        jobs =
+                            Some(
+                                value.parse::<usize>().ok().filter(|jobs| *jobs > 0).ok_or(
+                                    "Invalid --jobs, expected a positive number of threads",
+                                )?,
+                            )
+                    }
+                    NamedArg {
+                        name: "optimize",
+                        value: None,
+                    } => optimize = true,
+                    NamedArg {
+                        name: "allow-rotation",
+                        value: None,
+                    } => allow_rotation = true,
+                    NamedArg {
+                        name: "seed",
+                        value: Some(value),
+                    } => {
+                        seed = Some(
+                            value
+                                .parse::<u64>()
+                                .map_err(|_| "Invalid --seed, expected a whole number")?,
+                        )
+                    }
+                    NamedArg {
+                        name: "time-budget",
+                        value: Some(value),
+                    } => {
+                        time_budget = Some(
+                            value
+                                .parse::<f64>()
+                                .ok()
+                                .filter(|seconds| seconds.is_finite() && *seconds > 0.0)
+                                .map(Duration::from_secs_f64)
+                                .ok_or(
+                                    "Invalid --time-budget, expected a positive number of seconds",
+                                )?,
+                        )
+                    }
+                    NamedArg {
+                        name: "iterations",
+                        value: Some(value),
+                    } => {
+                        iterations = Some(
+                            value
+                                .parse::<u64>()
+                                .map_err(|_| "Invalid --iterations, expected a whole number")?,
+                        )
+                    }
+                    NamedArg {
+                        name: "target-occupancy",
+                        value: Some(value),
+                    } => {
+                        target_occupancy = Some(
+                            value
+                                .parse::<f64>()
+                                .ok()
+                                .filter(|target| *target > 0.0 && *target <= 1.0)
+                                .ok_or(
+                                    "Invalid --target-occupancy, expected a fraction like 0.9",
+                                )?,
+                        )
+                    }
                     NamedArg {
                         name: "help",
                         value: None,
                     } => {
-                        println!("A simple sprite sheet packer\n\nUsage: `corral input_dir output_sheet.png [--data-fmt=json|lua]");
+                        let params = named_params
+                            .iter()
+                            .filter(|param| param.name != "help")
+                            .map(|param| param.to_string())
+                            .collect::<Vec<String>>()
+                            .join(" ");
+                        println!("A simple sprite sheet packer\n\nUsage: `corral input_dir output_sheet.png|.ktx2|.dds|.exr {params}`\n       `corral unpack sheet.png sheet.json|.lua|.atlas out_dir`\n       `corral verify sheet.png sheet.json|.lua|.atlas [input_dir]`");
                         exit(0);
                     }
                     _ => {
-                        return Err("Unrecognised argument");
+                        return Err("Unrecognised argument".into());
                     }
                 }
             };
         }
 
         if args.len() < 3 {
-            return Err("Too few arguments, Usage: `corral input_dir output_sheet.png [--data-fmt=json|lua]`");
+            return Err("Too few arguments, Usage: `corral input_dir output_sheet.png [--data-fmt=json|lua]`".into());
         }
 
         let input_dir = args[1].clone();
         let output_file = args[2].clone();
+        let image_format = ImageFormat::from_file_name(&output_file);
+
+        if !image_format.is_gpu_texture() && compression != TextureCompression::None {
+            return Err("Block compression requires a .ktx2 or .dds output file".into());
+        }
+        if !image_format.is_gpu_texture() && mipmaps {
+            return Err("Mipmaps require a .ktx2 or .dds output file".into());
+        }
+        if image_format == ImageFormat::Dds && compression == TextureCompression::Etc2 {
+            return Err("ETC2 compression is only supported in .ktx2 output files".into());
+        }
+        if image_format != ImageFormat::Png && indexed {
+            return Err("Indexed output requires a .png output file".into());
+        }
+        if dither && !indexed {
+            return Err("Dithering requires --indexed or --palette".into());
+        }
+        if preserve_depth && image_format.is_gpu_texture() {
+            return Err("Preserving bit depth requires a .png or .exr output file".into());
+        }
+        if preserve_depth && indexed {
+            return Err("Preserving bit depth is not supported for indexed output".into());
+        }
+
+        let scale_suffixes: Vec<String> = match scale_suffixes {
+            Some(scale_suffixes) => scale_suffixes,
+            None => scales
+                .iter()
+                .map(|scale| {
+                    if *scale == 1.0 {
+                        String::new()
+                    } else {
+                        format!("@{scale}x")
+                    }
+                })
+                .collect(),
+        };
+        if scale_suffixes.len() != scales.len() {
+            return Err("--scale-suffixes needs one suffix for each of the --scales".into());
+        }
+        let mut unique_suffixes = scale_suffixes.clone();
+        unique_suffixes.sort();
+        unique_suffixes.dedup();
+        if unique_suffixes.len() != scale_suffixes.len() {
+            return Err("Every scale needs a different file suffix".into());
+        }
+
+        // premultiplying zeroes the colour of transparent pixels again
+        if alpha_bleed && premultiply_alpha {
+            return Err(
+                "--alpha-bleed has no effect with --premultiply-alpha, use one or the other".into(),
+            );
+        }
+
+        if allow_partial && sheet_size.is_none() {
+            return Err("--allow-partial requires --size".into());
+        }
+
+        let searching = seed.is_some()
+            || time_budget.is_some()
+            || iterations.is_some()
+            || target_occupancy.is_some()
+            || allow_rotation;
+        if searching && !optimize {
+            return Err(
+                "--seed, --time-budget, --iterations, --target-occupancy and --allow-rotation require --optimize".into(),
+            );
+        }
+        // without a limit the search runs for 10 seconds
+        let optimize = optimize.then(|| optimize::Settings {
+            seed: seed.unwrap_or(0),
+            time_budget: match (time_budget, iterations) {
+                (None, None) => Some(Duration::from_secs(10)),
+                _ => time_budget,
+            },
+            iterations,
+            target_occupancy,
+            allow_rotation,
+        });
+
+        let frame_pattern = Regex::new(frame_pattern)
+            .map_err(|_| "Invalid --frame-pattern, expected a regular expression")?;
+        let capture_names: Vec<&str> = frame_pattern.capture_names().flatten().collect();
+        if !capture_names.contains(&"name") || !capture_names.contains(&"frame") {
+            return Err("--frame-pattern needs a (?P<name>...) and a (?P<frame>...) group".into());
+        }
 
         Ok(Config {
             padding: 2u8,
             input_dir,
             output_file,
             output_file_format: metadata_format,
+            meta,
+            image_format,
+            compression,
+            mipmaps,
+            indexed,
+            dither,
+            palette_file,
+            preserve_depth,
+            premultiply_alpha,
+            alpha_bleed,
+            scales,
+            scale_suffixes,
+            filter,
+            frame_pattern,
+            split_layers,
+            grid_names,
+            report_file,
+            debug_image,
+            preview_html,
+            sort_key,
+            leaf_choice,
+            best,
+            jobs,
+            optimize,
+            stable_file,
+            sheet_size,
+            allow_partial,
+            grouping,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(flags: &[&str]) -> Result<Config, String> {
+        let args: Vec<String> = ["corral", "in", "out.png"]
+            .iter()
+            .chain(flags)
+            .map(|arg| arg.to_string())
+            .collect();
+        Config::parse(&args)
+    }
+
+    #[test]
+    fn rejects_values_outside_the_choices() {
+        assert_eq!(
+            parse(&["--sort=bogus"]).err(),
+            Some(
+                "Invalid --sort=bogus, expected one of area|max-side|perimeter|height|width|none"
+                    .to_owned()
+            )
+        );
+        assert!(parse(&["--leaf=widest"]).is_err());
+        assert!(parse(&["--data-fmt=xml"]).is_err());
+        assert!(parse(&["--filter=bicubic"]).is_err());
+
+        let args: Vec<String> = ["corral", "in", "out.dds", "--compression=bc5"]
+            .map(|arg| arg.to_owned())
+            .to_vec();
+        assert!(Config::parse(&args)
+            .err()
+            .is_some_and(|err| err.contains("--compression=bc5")));
+    }
+
+    #[test]
+    fn accepts_values_from_the_choices() -> Result<(), String> {
+        let config = parse(&["--sort=width", "--leaf=best-short-side"])?;
+        assert_eq!(config.sort_key, SortKey::Width);
+        assert_eq!(config.leaf_choice, LeafChoice::BestShortSide);
+        Ok(())
+    }
+}
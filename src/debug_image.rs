@@ -0,0 +1,226 @@
+use std::error::Error;
+
+use image::{Rgba, RgbaImage};
+
+use crate::bounding_box::BoundingBox;
+use crate::meta_data::SpriteData;
+
+const OUTLINE: Rgba<u8> = Rgba([0, 255, 0, 255]);
+const FREE_LEAF: Rgba<u8> = Rgba([255, 0, 255, 255]);
+const SPLIT: Rgba<u8> = Rgba([0, 255, 255, 255]);
+const LABEL: Rgba<u8> = Rgba([255, 255, 255, 255]);
+const LABEL_BACKGROUND: Rgba<u8> = Rgba([0, 0, 0, 255]);
+
+// free leaves and splits in the coordinates of the tree, which sit `padding`
+// pixels up and left of the sheet
+#[derive(Default)]
+pub struct Layout {
+    pub free_leaves: Vec<BoundingBox>,
+    pub splits: Vec<(BoundingBox, BoundingBox)>,
+}
+
+// 3x5 pixel glyphs, the top row in the highest bits
+fn glyph(c: char) -> u16 {
+    match c.to_ascii_uppercase() {
+        '0' => 0b111_101_101_101_111,
+        '1' => 0b010_110_010_010_111,
+        '2' => 0b111_001_111_100_111,
+        '3' => 0b111_001_111_001_111,
+        '4' => 0b101_101_111_001_001,
+        '5' => 0b111_100_111_001_111,
+        '6' => 0b111_100_111_101_111,
+        '7' => 0b111_001_001_001_001,
+        '8' => 0b111_101_111_101_111,
+        '9' => 0b111_101_111_001_111,
+        'A' => 0b010_101_111_101_101,
+        'B' => 0b110_101_110_101_110,
+        'C' => 0b011_100_100_100_011,
+        'D' => 0b110_101_101_101_110,
+        'E' => 0b111_100_110_100_111,
+        'F' => 0b111_100_110_100_100,
+        'G' => 0b011_100_101_101_011,
+        'H' => 0b101_101_111_101_101,
+        'I' => 0b111_010_010_010_111,
+        'J' => 0b001_001_001_101_010,
+        'K' => 0b101_101_110_101_101,
+        'L' => 0b100_100_100_100_111,
+        'M' => 0b101_111_111_101_101,
+        'N' => 0b110_101_101_101_101,
+        'O' => 0b010_101_101_101_010,
+        'P' => 0b110_101_110_100_100,
+        'Q' => 0b010_101_101_110_011,
+        'R' => 0b110_101_110_101_101,
+        'S' => 0b011_100_010_001_110,
+        'T' => 0b111_010_010_010_010,
+        'U' => 0b101_101_101_101_111,
+        'V' => 0b101_101_101_101_010,
+        'W' => 0b101_101_111_111_101,
+        'X' => 0b101_101_010_101_101,
+        'Y' => 0b101_101_010_010_010,
+        'Z' => 0b111_001_010_100_111,
+        '_' => 0b000_000_000_000_111,
+        '-' => 0b000_000_111_000_000,
+        '.' => 0b000_000_000_000_010,
+        '/' => 0b001_001_010_100_100,
+        ' ' => 0,
+        _ => 0b111_001_010_000_010,
+    }
+}
+
+fn put(img: &mut RgbaImage, x: u32, y: u32, colour: Rgba<u8>) {
+    if x < img.width() && y < img.height() {
+        img.put_pixel(x, y, colour);
+    }
+}
+
+fn blend(img: &mut RgbaImage, x: u32, y: u32, colour: Rgba<u8>) {
+    if x < img.width() && y < img.height() {
+        let pixel = img.get_pixel_mut(x, y);
+        for c in 0..3 {
+            pixel[c] = ((pixel[c] as u16 + colour[c] as u16) / 2) as u8;
+        }
+        pixel[3] = pixel[3].max(128);
+    }
+}
+
+fn outline(img: &mut RgbaImage, sd: &SpriteData) {
+    let bb = sd.bounding_box();
+    if bb.width == 0 || bb.height == 0 {
+        return;
+    }
+    let (x1, y1) = (bb.x + bb.width - 1, bb.y + bb.height - 1);
+    for x in bb.x..=x1 {
+        put(img, x, bb.y, OUTLINE);
+        put(img, x, y1, OUTLINE);
+    }
+    for y in bb.y..=y1 {
+        put(img, bb.x, y, OUTLINE);
+        put(img, x1, y, OUTLINE);
+    }
+}
+
+// as much of the name as fits inside the outline
+fn label(img: &mut RgbaImage, sd: &SpriteData) {
+    let bb = sd.bounding_box();
+    if bb.width < 6 || bb.height < 8 {
+        return;
+    }
+    let fits = ((bb.width - 2) / 4) as usize;
+    let text: Vec<char> = sd.name.chars().take(fits).collect();
+    let (x0, y0) = (sd.x + 1, sd.y + 1);
+    for y in y0..y0 + 7 {
+        for x in x0..x0 + text.len() as u32 * 4 + 1 {
+            put(img, x, y, LABEL_BACKGROUND);
+        }
+    }
+    for (i, c) in text.into_iter().enumerate() {
+        let bits = glyph(c);
+        for row in 0..5 {
+            for column in 0..3 {
+                if bits >> (14 - (row * 3 + column)) & 1 == 1 {
+                    put(img, x0 + 1 + i as u32 * 4 + column, y0 + 1 + row, LABEL);
+                }
+            }
+        }
+    }
+}
+
+pub fn draw(
+    sheet: &RgbaImage,
+    sprite_data: &[SpriteData],
+    layout: &Layout,
+    padding: u32,
+) -> RgbaImage {
+    let mut img = sheet.clone();
+    let (width, height) = img.dimensions();
+
+    for leaf in &layout.free_leaves {
+        let x_end = (leaf.x as u64 + leaf.width as u64 + padding as u64).min(width as u64) as u32;
+        let y_end = (leaf.y as u64 + leaf.height as u64 + padding as u64).min(height as u64) as u32;
+        for y in leaf.y + padding..y_end {
+            for x in leaf.x + padding..x_end {
+                blend(&mut img, x, y, FREE_LEAF);
+            }
+        }
+    }
+
+    // cuts run through the middle of the padding between partitions
+    let offset = padding / 2;
+    for (right, down) in &layout.splits {
+        let y_end =
+            (right.y as u64 + right.height as u64 + offset as u64).min(height as u64) as u32;
+        for y in right.y + offset..y_end {
+            put(&mut img, right.x + offset, y, SPLIT);
+        }
+        let x_end = (down.x as u64 + down.width as u64 + offset as u64).min(width as u64) as u32;
+        for x in down.x + offset..x_end {
+            put(&mut img, x, down.y + offset, SPLIT);
+        }
+    }
+
+    for sd in sprite_data {
+        outline(&mut img, sd);
+    }
+    for sd in sprite_data {
+        label(&mut img, sd);
+    }
+    img
+}
+
+pub fn write(
+    debug_file: &str,
+    sheet: &RgbaImage,
+    sprite_data: &[SpriteData],
+    layout: &Layout,
+    padding: u32,
+) -> Result<(), Box<dyn Error>> {
+    draw(sheet, sprite_data, layout, padding).save(debug_file)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn outlines_labels_and_free_space() {
+        // one 12x10 sprite packed with 2px padding, free space right and below
+        let sheet = RgbaImage::new(24, 24);
+        let sprite = SpriteData {
+            name: "a1".to_owned(),
+            x: 2,
+            y: 2,
+            width: 12,
+            height: 10,
+            ..Default::default()
+        };
+        let right = BoundingBox {
+            x: 14,
+            y: 0,
+            width: u32::MAX - 14,
+            height: 12,
+        };
+        let down = BoundingBox {
+            x: 0,
+            y: 12,
+            width: u32::MAX,
+            height: u32::MAX - 12,
+        };
+        let layout = Layout {
+            free_leaves: vec![right, down],
+            splits: vec![(right, down)],
+        };
+        let img = draw(&sheet, &[sprite], &layout, 2);
+
+        assert_eq!(img.get_pixel(2, 11), &OUTLINE);
+        assert_eq!(img.get_pixel(13, 5), &OUTLINE);
+        assert_eq!(img.get_pixel(3, 3), &LABEL_BACKGROUND);
+        // the tops of the A and the 1, with a gap between them
+        assert_eq!(img.get_pixel(5, 4), &LABEL);
+        assert_eq!(img.get_pixel(8, 4), &LABEL_BACKGROUND);
+        assert_eq!(img.get_pixel(9, 4), &LABEL);
+        assert_eq!(img.get_pixel(15, 5), &SPLIT);
+        assert_eq!(img.get_pixel(5, 13), &SPLIT);
+        assert_eq!(img.get_pixel(20, 20), &Rgba([127, 0, 127, 128]));
+    }
+}
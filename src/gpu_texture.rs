@@ -0,0 +1,400 @@
+use std::error::Error;
+use std::fs;
+
+use image::{imageops::FilterType, RgbaImage};
+
+use crate::block_compression;
+use crate::config::{ImageFormat, TextureCompression};
+
+const KTX2_IDENTIFIER: [u8; 12] = [
+    0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+];
+
+const DDSD_CAPS: u32 = 0x1;
+const DDSD_HEIGHT: u32 = 0x2;
+const DDSD_WIDTH: u32 = 0x4;
+const DDSD_PITCH: u32 = 0x8;
+const DDSD_PIXELFORMAT: u32 = 0x1000;
+const DDSD_MIPMAPCOUNT: u32 = 0x20000;
+const DDSD_LINEARSIZE: u32 = 0x80000;
+const DDPF_ALPHAPIXELS: u32 = 0x1;
+const DDPF_FOURCC: u32 = 0x4;
+const DDPF_RGB: u32 = 0x40;
+const DDSCAPS_COMPLEX: u32 = 0x8;
+const DDSCAPS_TEXTURE: u32 = 0x1000;
+const DDSCAPS_MIPMAP: u32 = 0x400000;
+
+struct Level {
+    width: u32,
+    height: u32,
+    data: Vec<u8>,
+}
+
+pub fn write(
+    output_file: &str,
+    img: &RgbaImage,
+    image_format: ImageFormat,
+    compression: TextureCompression,
+    mipmaps: bool,
+    premultiplied: bool,
+) -> Result<(), Box<dyn Error>> {
+    let levels: Vec<Level> = mip_chain(img, mipmaps)
+        .iter()
+        .map(|level| Level {
+            width: level.width(),
+            height: level.height(),
+            data: block_compression::compress(level, compression),
+        })
+        .collect();
+
+    let bytes = match image_format {
+        ImageFormat::Ktx2 => ktx2_bytes(&levels, compression, premultiplied),
+        ImageFormat::Dds => dds_bytes(&levels, compression, premultiplied),
+        ImageFormat::Png | ImageFormat::Exr => {
+            return Err("only .ktx2 and .dds outputs are gpu texture containers".into())
+        }
+    };
+    fs::write(output_file, bytes)?;
+    Ok(())
+}
+
+fn mip_chain(img: &RgbaImage, mipmaps: bool) -> Vec<RgbaImage> {
+    let mut levels = vec![img.clone()];
+    if !mipmaps {
+        return levels;
+    }
+    while let Some(last) = levels.last() {
+        if last.width() == 1 && last.height() == 1 {
+            break;
+        }
+        let width = (last.width() / 2).max(1);
+        let height = (last.height() / 2).max(1);
+        let next = image::imageops::resize(last, width, height, FilterType::Triangle);
+        levels.push(next);
+    }
+    levels
+}
+
+fn push_u32(bytes: &mut Vec<u8>, value: u32) {
+    bytes.extend_from_slice(&value.to_le_bytes());
+}
+
+fn push_u64(bytes: &mut Vec<u8>, value: u64) {
+    bytes.extend_from_slice(&value.to_le_bytes());
+}
+
+fn vk_format(compression: TextureCompression) -> u32 {
+    match compression {
+        TextureCompression::None => 37,  // VK_FORMAT_R8G8B8A8_UNORM
+        TextureCompression::Bc1 => 133,  // VK_FORMAT_BC1_RGBA_UNORM_BLOCK
+        TextureCompression::Bc3 => 137,  // VK_FORMAT_BC3_UNORM_BLOCK
+        TextureCompression::Bc7 => 145,  // VK_FORMAT_BC7_UNORM_BLOCK
+        TextureCompression::Etc2 => 151, // VK_FORMAT_ETC2_R8G8B8A8_UNORM_BLOCK
+    }
+}
+
+fn data_format_descriptor(compression: TextureCompression, premultiplied: bool) -> Vec<u8> {
+    // (channel, bit offset, bit length, upper)
+    let (colour_model, samples): (u32, Vec<(u32, u32, u32, u32)>) = match compression {
+        TextureCompression::None => (
+            1,
+            vec![
+                (0, 0, 8, 255),
+                (1, 8, 8, 255),
+                (2, 16, 8, 255),
+                (15, 24, 8, 255),
+            ],
+        ),
+        // bc1 has no alpha channel of its own, just the punch through flag
+        TextureCompression::Bc1 => (128, vec![(1, 0, 64, u32::MAX)]),
+        TextureCompression::Bc3 => (130, vec![(15, 0, 64, u32::MAX), (0, 64, 64, u32::MAX)]),
+        TextureCompression::Bc7 => (134, vec![(0, 0, 128, u32::MAX)]),
+        TextureCompression::Etc2 => (161, vec![(15, 0, 64, u32::MAX), (2, 64, 64, u32::MAX)]),
+    };
+    let block_dimension = compression.block_dimension() - 1;
+    let block_size = 24 + 16 * samples.len() as u32;
+
+    let mut bytes = vec![];
+    push_u32(&mut bytes, 4 + block_size);
+    push_u32(&mut bytes, 0);
+    push_u32(&mut bytes, 2 | (block_size << 16));
+    // linear transfer function, bt709 primaries, straight or premultiplied alpha
+    let flags = if premultiplied { 1 } else { 0 };
+    push_u32(
+        &mut bytes,
+        colour_model | (1 << 8) | (1 << 16) | (flags << 24),
+    );
+    push_u32(&mut bytes, block_dimension | (block_dimension << 8));
+    push_u32(&mut bytes, compression.block_bytes() as u32);
+    push_u32(&mut bytes, 0);
+    for (channel, offset, length, upper) in samples {
+        push_u32(&mut bytes, offset | ((length - 1) << 16) | (channel << 24));
+        push_u32(&mut bytes, 0);
+        push_u32(&mut bytes, 0);
+        push_u32(&mut bytes, upper);
+    }
+    bytes
+}
+
+fn ktx2_bytes(levels: &[Level], compression: TextureCompression, premultiplied: bool) -> Vec<u8> {
+    let dfd = data_format_descriptor(compression, premultiplied);
+    let level_index_offset = 80;
+    let dfd_offset = level_index_offset + 24 * levels.len();
+    let alignment = if compression == TextureCompression::None {
+        4
+    } else {
+        compression.block_bytes()
+    };
+
+    // level data is stored smallest mip first
+    let mut offsets = vec![0usize; levels.len()];
+    let mut offset = dfd_offset + dfd.len();
+    for (i, level) in levels.iter().enumerate().rev() {
+        offset = offset.div_ceil(alignment) * alignment;
+        offsets[i] = offset;
+        offset += level.data.len();
+    }
+
+    let mut bytes = KTX2_IDENTIFIER.to_vec();
+    push_u32(&mut bytes, vk_format(compression));
+    push_u32(&mut bytes, 1);
+    push_u32(&mut bytes, levels[0].width);
+    push_u32(&mut bytes, levels[0].height);
+    push_u32(&mut bytes, 0);
+    push_u32(&mut bytes, 0);
+    push_u32(&mut bytes, 1);
+    push_u32(&mut bytes, levels.len() as u32);
+    push_u32(&mut bytes, 0);
+
+    push_u32(&mut bytes, dfd_offset as u32);
+    push_u32(&mut bytes, dfd.len() as u32);
+    push_u32(&mut bytes, 0);
+    push_u32(&mut bytes, 0);
+    push_u64(&mut bytes, 0);
+    push_u64(&mut bytes, 0);
+
+    for (level, offset) in levels.iter().zip(offsets.iter()) {
+        push_u64(&mut bytes, *offset as u64);
+        push_u64(&mut bytes, level.data.len() as u64);
+        push_u64(&mut bytes, level.data.len() as u64);
+    }
+
+    bytes.extend_from_slice(&dfd);
+    for (i, level) in levels.iter().enumerate().rev() {
+        bytes.resize(offsets[i], 0);
+        bytes.extend_from_slice(&level.data);
+    }
+    bytes
+}
+
+fn dxgi_format(compression: TextureCompression) -> u32 {
+    match compression {
+        TextureCompression::Bc1 => 71, // DXGI_FORMAT_BC1_UNORM
+        TextureCompression::Bc3 => 77, // DXGI_FORMAT_BC3_UNORM
+        TextureCompression::Bc7 => 98, // DXGI_FORMAT_BC7_UNORM
+        _ => 28,                       // DXGI_FORMAT_R8G8B8A8_UNORM
+    }
+}
+
+fn dds_bytes(levels: &[Level], compression: TextureCompression, premultiplied: bool) -> Vec<u8> {
+    let (width, height) = (levels[0].width, levels[0].height);
+    let mipmapped = levels.len() > 1;
+
+    let mut flags = DDSD_CAPS | DDSD_HEIGHT | DDSD_WIDTH | DDSD_PIXELFORMAT;
+    let pitch_or_linear_size = if compression == TextureCompression::None {
+        flags |= DDSD_PITCH;
+        width * 4
+    } else {
+        flags |= DDSD_LINEARSIZE;
+        levels[0].data.len() as u32
+    };
+    if mipmapped {
+        flags |= DDSD_MIPMAPCOUNT;
+    }
+
+    let mut bytes = b"DDS ".to_vec();
+    push_u32(&mut bytes, 124);
+    push_u32(&mut bytes, flags);
+    push_u32(&mut bytes, height);
+    push_u32(&mut bytes, width);
+    push_u32(&mut bytes, pitch_or_linear_size);
+    push_u32(&mut bytes, 0);
+    push_u32(&mut bytes, levels.len() as u32);
+    bytes.resize(bytes.len() + 11 * 4, 0);
+
+    // the legacy headers can't mark premultiplied alpha, so premultiplied
+    // sheets always get the DX10 header
+    let dx10 = compression == TextureCompression::Bc7 || premultiplied;
+    push_u32(&mut bytes, 32);
+    match compression {
+        TextureCompression::None if !dx10 => {
+            push_u32(&mut bytes, DDPF_RGB | DDPF_ALPHAPIXELS);
+            push_u32(&mut bytes, 0);
+            push_u32(&mut bytes, 32);
+            push_u32(&mut bytes, 0x000000ff);
+            push_u32(&mut bytes, 0x0000ff00);
+            push_u32(&mut bytes, 0x00ff0000);
+            push_u32(&mut bytes, 0xff000000);
+        }
+        _ => {
+            let four_cc = match compression {
+                _ if dx10 => b"DX10",
+                TextureCompression::Bc1 => b"DXT1",
+                _ => b"DXT5",
+            };
+            push_u32(&mut bytes, DDPF_FOURCC);
+            bytes.extend_from_slice(four_cc);
+            bytes.resize(bytes.len() + 5 * 4, 0);
+        }
+    }
+
+    let mut caps = DDSCAPS_TEXTURE;
+    if mipmapped {
+        caps |= DDSCAPS_COMPLEX | DDSCAPS_MIPMAP;
+    }
+    push_u32(&mut bytes, caps);
+    bytes.resize(bytes.len() + 4 * 4, 0);
+
+    if dx10 {
+        push_u32(&mut bytes, dxgi_format(compression));
+        push_u32(&mut bytes, 3); // D3D10_RESOURCE_DIMENSION_TEXTURE2D
+        push_u32(&mut bytes, 0);
+        push_u32(&mut bytes, 1);
+        // DDS_ALPHA_MODE_PREMULTIPLIED, or straight
+        push_u32(&mut bytes, if premultiplied { 2 } else { 0 });
+    }
+
+    for level in levels {
+        bytes.extend_from_slice(&level.data);
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checkerboard(w: u32, h: u32) -> RgbaImage {
+        let mut img = RgbaImage::new(w, h);
+        for (x, y, pixel) in img.enumerate_pixels_mut() {
+            if (x / 4 + y / 4) % 2 == 0 {
+                *pixel = image::Rgba([255, 0, 0, 255]);
+            } else {
+                *pixel = image::Rgba([0, 0, 255, 255]);
+            }
+        }
+        img
+    }
+
+    #[test]
+    fn mip_chain_ends_at_one_pixel() {
+        let levels = mip_chain(&checkerboard(16, 4), true);
+        let sizes: Vec<(u32, u32)> = levels.iter().map(|l| (l.width(), l.height())).collect();
+        assert_eq!(sizes, vec![(16, 4), (8, 2), (4, 1), (2, 1), (1, 1)]);
+    }
+
+    #[test]
+    fn dds_bc1_decodes() -> Result<(), Box<dyn Error>> {
+        let img = checkerboard(16, 8);
+        let levels = vec![Level {
+            width: 16,
+            height: 8,
+            data: block_compression::compress(&img, TextureCompression::Bc1),
+        }];
+        let bytes = dds_bytes(&levels, TextureCompression::Bc1, false);
+        let decoded = image::load_from_memory_with_format(&bytes, image::ImageFormat::Dds)?;
+        assert_eq!(decoded.to_rgba8().as_raw(), img.as_raw());
+        Ok(())
+    }
+
+    #[test]
+    fn dds_bc3_decodes() -> Result<(), Box<dyn Error>> {
+        let mut img = checkerboard(8, 8);
+        for (x, y, pixel) in img.enumerate_pixels_mut() {
+            pixel[3] = (x * 32 + y * 4) as u8;
+        }
+        let levels = vec![Level {
+            width: 8,
+            height: 8,
+            data: block_compression::compress(&img, TextureCompression::Bc3),
+        }];
+        let bytes = dds_bytes(&levels, TextureCompression::Bc3, false);
+        let decoded = image::load_from_memory_with_format(&bytes, image::ImageFormat::Dds)?;
+        for (p, q) in img.pixels().zip(decoded.to_rgba8().pixels()) {
+            for c in 0..4 {
+                assert!((p[c] as i32 - q[c] as i32).abs() <= 8);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn dds_marks_premultiplied_alpha() {
+        let read_u32 =
+            |bytes: &[u8], at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+        let img = checkerboard(8, 8);
+        for (compression, dxgi) in [
+            (TextureCompression::Bc1, 71),
+            (TextureCompression::Bc3, 77),
+            (TextureCompression::None, 28),
+        ] {
+            let data = match compression {
+                TextureCompression::None => img.as_raw().clone(),
+                _ => block_compression::compress(&img, compression),
+            };
+            let levels = vec![Level {
+                width: 8,
+                height: 8,
+                data,
+            }];
+            let bytes = dds_bytes(&levels, compression, true);
+            assert_eq!(&bytes[80..88], &[4, 0, 0, 0, b'D', b'X', b'1', b'0']);
+            assert_eq!(read_u32(&bytes, 128), dxgi);
+            assert_eq!(read_u32(&bytes, 144), 2);
+            assert_eq!(bytes.len(), 148 + levels[0].data.len());
+
+            let straight = dds_bytes(&levels, compression, false);
+            assert_ne!(&straight[84..88], b"DX10");
+        }
+    }
+
+    #[test]
+    fn ktx2_layout() {
+        let img = checkerboard(8, 8);
+        let levels: Vec<Level> = mip_chain(&img, true)
+            .iter()
+            .map(|level| Level {
+                width: level.width(),
+                height: level.height(),
+                data: block_compression::compress(level, TextureCompression::Bc7),
+            })
+            .collect();
+        let bytes = ktx2_bytes(&levels, TextureCompression::Bc7, false);
+        let read_u32 = |at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+        let read_u64 = |at: usize| u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap());
+
+        assert_eq!(bytes[0..12], KTX2_IDENTIFIER);
+        assert_eq!(read_u32(12), 145);
+        assert_eq!(read_u32(40), 4);
+
+        let level_0_offset = read_u64(80) as usize;
+        let level_0_length = read_u64(88) as usize;
+        assert_eq!(level_0_offset % 16, 0);
+        assert_eq!(level_0_length, 4 * 16);
+        assert_eq!(level_0_offset + level_0_length, bytes.len());
+    }
+
+    #[test]
+    fn dfd_channels_and_alpha_flag() {
+        let read_u32 =
+            |bytes: &[u8], at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+        let straight = data_format_descriptor(TextureCompression::Bc1, false);
+        assert_eq!(read_u32(&straight, 12) >> 24, 0);
+        // the channel of the first sample, KHR_DF_CHANNEL_BC1A_ALPHAPRESENT
+        assert_eq!(read_u32(&straight, 28) >> 24 & 15, 1);
+
+        let premultiplied = data_format_descriptor(TextureCompression::Bc3, true);
+        assert_eq!(read_u32(&premultiplied, 12) >> 24, 1);
+        assert_eq!(read_u32(&premultiplied, 28) >> 24 & 15, 15);
+    }
+}
@@ -0,0 +1,125 @@
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use image::{DynamicImage, GenericImageView};
+use regex::Regex;
+use serde::Deserialize;
+
+#[derive(Debug, PartialEq, Eq, Deserialize)]
+pub struct Grid {
+    cell_width: u32,
+    cell_height: u32,
+    #[serde(default)]
+    spacing: u32,
+    #[serde(default)]
+    margin: u32,
+    frames: Option<u32>,
+}
+
+// explosion.png is described by explosion.grid.toml next to it
+fn sidecar_path(path: &Path) -> Option<PathBuf> {
+    let stem = path.file_prefix()?.to_string_lossy().to_string();
+    Some(path.with_file_name(format!("{stem}.grid.toml")))
+}
+
+pub fn from_sidecar(path: &Path) -> Result<Option<Grid>, Box<dyn Error>> {
+    match sidecar_path(path) {
+        Some(sidecar) if sidecar.exists() => {
+            let grid: Grid = toml::from_str(&fs::read_to_string(sidecar)?)?;
+            if grid.cell_width == 0 || grid.cell_height == 0 {
+                return Err("Grid cells need a width and height".into());
+            }
+            Ok(Some(grid))
+        }
+        _ => Ok(None),
+    }
+}
+
+// explosion_8x1 is a strip of 8 columns and 1 row, the name loses the grid suffix
+pub fn from_name(name: &str, width: u32, height: u32) -> Option<(String, Grid)> {
+    let pattern = Regex::new(r"^(?P<name>.+)_(?P<columns>\d+)x(?P<rows>\d+)$").ok()?;
+    let captures = pattern.captures(name)?;
+    let columns: u32 = captures["columns"].parse().ok()?;
+    let rows: u32 = captures["rows"].parse().ok()?;
+    if columns == 0 || rows == 0 || !width.is_multiple_of(columns) || !height.is_multiple_of(rows) {
+        return None;
+    }
+    Some((
+        captures["name"].to_owned(),
+        Grid {
+            cell_width: width / columns,
+            cell_height: height / rows,
+            spacing: 0,
+            margin: 0,
+            frames: None,
+        },
+    ))
+}
+
+// cells are cut row by row and keep their index in the name even when empty
+// cells before them are skipped
+pub fn slice(name: &str, img: &DynamicImage, grid: &Grid) -> Vec<(String, DynamicImage)> {
+    let cells_along = |size: u32, cell: u32| {
+        (size + grid.spacing).saturating_sub(grid.margin * 2) / (cell + grid.spacing)
+    };
+    let columns = cells_along(img.width(), grid.cell_width);
+    let rows = cells_along(img.height(), grid.cell_height);
+    let count = grid.frames.unwrap_or(columns * rows).min(columns * rows);
+
+    let mut cells = vec![];
+    for i in 0..count {
+        let x = grid.margin + (i % columns) * (grid.cell_width + grid.spacing);
+        let y = grid.margin + (i / columns) * (grid.cell_height + grid.spacing);
+        let cell = img.view(x, y, grid.cell_width, grid.cell_height);
+        if cell.pixels().all(|(_, _, pixel)| pixel[3] == 0) {
+            continue;
+        }
+        cells.push((
+            format!("{name}_{i}"),
+            img.crop_imm(x, y, grid.cell_width, grid.cell_height),
+        ));
+    }
+    cells
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grid_from_name() {
+        let (name, grid) = from_name("explosion_8x2", 256, 64).unwrap();
+        assert_eq!(name, "explosion");
+        assert_eq!((grid.cell_width, grid.cell_height), (32, 32));
+        assert!(from_name("explosion_3x1", 256, 64).is_none());
+        assert!(from_name("explosion", 256, 64).is_none());
+    }
+
+    #[test]
+    fn slice_with_spacing_and_margin() {
+        // 3 columns by 2 rows of 4x4 cells, 1px margin and 2px spacing, the middle top cell empty
+        let mut img = image::RgbaImage::new(1 + 4 * 3 + 2 * 2 + 1, 1 + 4 * 2 + 2 + 1);
+        for (x, y, pixel) in img.enumerate_pixels_mut() {
+            let in_cell = |v: u32| v >= 1 && (v - 1) % 6 < 4;
+            let middle_top = (7..11).contains(&x) && y < 6;
+            if in_cell(x) && in_cell(y) && !middle_top {
+                *pixel = image::Rgba([255, 0, 0, 255]);
+            }
+        }
+        let grid = Grid {
+            cell_width: 4,
+            cell_height: 4,
+            spacing: 2,
+            margin: 1,
+            frames: Some(5),
+        };
+        let cells = slice("boom", &DynamicImage::ImageRgba8(img), &grid);
+        let names: Vec<&str> = cells.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, vec!["boom_0", "boom_2", "boom_3", "boom_4"]);
+        for (_, cell) in &cells {
+            assert_eq!((cell.width(), cell.height()), (4, 4));
+            assert!(cell.pixels().all(|(_, _, pixel)| pixel[3] == 255));
+        }
+    }
+}
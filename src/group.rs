@@ -0,0 +1,324 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::config::MetaDataFormat;
+use crate::meta_data::{json_name, lua_name};
+
+pub enum Grouping {
+    // every top level subdirectory of the input is a group
+    Directories,
+    // groups are assigned by the glob rules in this toml file
+    Rules(String),
+}
+
+// files directly in the input directory that no rule claims belong to the
+// group with an empty name, written to the plain output file
+pub struct Group {
+    pub name: String,
+    pub dir: PathBuf,
+    pub paths: Vec<PathBuf>,
+}
+
+pub struct Sheet {
+    pub image: String,
+    pub data: String,
+    pub scale: f32,
+    pub width: u32,
+    pub height: u32,
+}
+
+pub struct IndexEntry {
+    pub name: String,
+    pub sheets: Vec<Sheet>,
+    pub sprites: Vec<String>,
+}
+
+// a group name and the compiled patterns of its files
+type GroupPatterns = (String, Vec<Regex>);
+
+#[derive(Deserialize)]
+struct Rules {
+    group: Vec<Rule>,
+}
+
+#[derive(Deserialize)]
+struct Rule {
+    name: String,
+    files: Vec<String>,
+}
+
+// `*` and `?` stay within a directory, `**` crosses directories
+pub fn glob_regex(pattern: &str) -> Result<Regex, regex::Error> {
+    let mut expression = "^".to_owned();
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                if chars.peek() == Some(&'/') {
+                    chars.next();
+                    expression.push_str("(?:.*/)?");
+                } else {
+                    expression.push_str(".*");
+                }
+            }
+            '*' => expression.push_str("[^/]*"),
+            '?' => expression.push_str("[^/]"),
+            c => expression.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    expression.push('$');
+    Regex::new(&expression)
+}
+
+// every file below `dir`, sorted as read_dir order depends on the file system
+fn list_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), Box<dyn Error>> {
+    let mut paths = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    paths.sort();
+    for path in paths {
+        if path.is_dir() {
+            list_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn read_rules(rules_file: &str) -> Result<Vec<GroupPatterns>, Box<dyn Error>> {
+    let rules: Rules = toml::from_str(&fs::read_to_string(rules_file)?)?;
+    let mut result = vec![];
+    for rule in rules.group {
+        if rule.name.is_empty() || rule.name.contains(['/', '\\']) {
+            return Err(format!("Invalid group name \"{}\" in {rules_file}", rule.name).into());
+        }
+        let patterns = rule
+            .files
+            .iter()
+            .map(|pattern| glob_regex(pattern))
+            .collect::<Result<Vec<Regex>, _>>()?;
+        result.push((rule.name, patterns));
+    }
+    Ok(result)
+}
+
+// the first rule with a pattern matching the path relative to the input wins,
+// anything else falls back to its top level directory
+fn assign_file(relative: &str, rules: &[GroupPatterns]) -> String {
+    let by_rule = rules
+        .iter()
+        .find(|(_, patterns)| patterns.iter().any(|pattern| pattern.is_match(relative)));
+    match (by_rule, relative.split_once('/')) {
+        (Some((name, _)), _) => name.to_owned(),
+        (None, Some((dir, _))) => dir.to_owned(),
+        (None, None) => String::new(),
+    }
+}
+
+pub fn assign(input_dir: &str, grouping: &Grouping) -> Result<Vec<Group>, Box<dyn Error>> {
+    let rules = match grouping {
+        Grouping::Directories => vec![],
+        Grouping::Rules(rules_file) => read_rules(rules_file)?,
+    };
+    let input_path = Path::new(input_dir);
+    let mut files = vec![];
+    list_files(input_path, &mut files)?;
+
+    let mut groups: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+    for path in files {
+        let relative = path
+            .strip_prefix(input_path)?
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        groups
+            .entry(assign_file(&relative, &rules))
+            .or_default()
+            .push(path);
+    }
+    for (name, _) in &rules {
+        if !groups.contains_key(name) {
+            println!("Warning: group {name} matched no files");
+        }
+    }
+    if groups.contains_key("index") {
+        return Err("A group can't be called index, that name is taken by the group index".into());
+    }
+
+    Ok(groups
+        .into_iter()
+        .map(|(name, paths)| {
+            // animation timings come from the group's own directory when it has one
+            let dir = match input_path.join(&name) {
+                dir if !name.is_empty() && dir.is_dir() => dir,
+                _ => input_path.to_owned(),
+            };
+            Group { name, dir, paths }
+        })
+        .collect())
+}
+
+fn file_name(path: &str) -> String {
+    Path::new(path)
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string()
+}
+
+fn quoted(text: &str) -> String {
+    serde_json::Value::from(text).to_string()
+}
+
+// files are listed relative to the index, which sits next to the sheets
+pub fn format_index(format: MetaDataFormat, entries: &[IndexEntry]) -> String {
+    match format {
+        MetaDataFormat::Json => {
+            let groups = entries
+                .iter()
+                .map(|entry| {
+                    let sheets = entry
+                        .sheets
+                        .iter()
+                        .map(|sheet| {
+                            format!(
+                                "{{\"data\":{},\"height\":{},\"image\":{},\"scale\":{},\"width\":{}}}",
+                                quoted(&file_name(&sheet.data)),
+                                sheet.height,
+                                quoted(&file_name(&sheet.image)),
+                                sheet.scale,
+                                sheet.width
+                            )
+                        })
+                        .collect::<Vec<String>>()
+                        .join(",");
+                    let sprites = entry
+                        .sprites
+                        .iter()
+                        .map(|name| quoted(&json_name(name)))
+                        .collect::<Vec<String>>()
+                        .join(",");
+                    format!(
+                        "{{\"name\":{},\"sheets\":[{sheets}],\"sprites\":[{sprites}]}}",
+                        quoted(&entry.name)
+                    )
+                })
+                .collect::<Vec<String>>()
+                .join(",");
+            format!("{{\"groups\":[{groups}]}}\n")
+        }
+        MetaDataFormat::Lua => {
+            let groups = entries
+                .iter()
+                .map(|entry| {
+                    let sheets = entry
+                        .sheets
+                        .iter()
+                        .map(|sheet| {
+                            format!(
+                                "            {{ image = {}, data = {}, scale = {}, width = {}, height = {} }},\n",
+                                quoted(&file_name(&sheet.image)),
+                                quoted(&file_name(&sheet.data)),
+                                sheet.scale,
+                                sheet.width,
+                                sheet.height
+                            )
+                        })
+                        .collect::<String>();
+                    let sprites = entry
+                        .sprites
+                        .iter()
+                        .map(|name| quoted(&lua_name(name)))
+                        .collect::<Vec<String>>()
+                        .join(", ");
+                    format!(
+                        "    {{\n        name = {},\n        sheets = {{\n{sheets}        }},\n        sprites = {{ {sprites} }},\n    }},\n",
+                        quoted(&entry.name)
+                    )
+                })
+                .collect::<String>();
+            format!("return {{\n{groups}}}\n")
+        }
+    }
+}
+
+pub fn write_index(
+    index_file: &str,
+    format: MetaDataFormat,
+    entries: &[IndexEntry],
+) -> Result<(), Box<dyn Error>> {
+    fs::write(index_file, format_index(format, entries))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn globs_match_within_and_across_directories() -> Result<(), regex::Error> {
+        let ui = glob_regex("ui/**")?;
+        assert!(ui.is_match("ui/button.png"));
+        assert!(ui.is_match("ui/icons/save.png"));
+        assert!(!ui.is_match("hud/ui.png"));
+
+        let hud = glob_regex("hud_?.png")?;
+        assert!(hud.is_match("hud_1.png"));
+        assert!(!hud.is_match("hud_10.png"));
+        assert!(!hud.is_match("hud_1xpng"));
+
+        let pngs = glob_regex("**/*.png")?;
+        assert!(pngs.is_match("a.png"));
+        assert!(pngs.is_match("fx/smoke/a.png"));
+        assert!(!glob_regex("*.png")?.is_match("fx/a.png"));
+        Ok(())
+    }
+
+    #[test]
+    fn first_rule_wins_then_top_level_directory() -> Result<(), regex::Error> {
+        let rules = vec![
+            (
+                "ui".to_owned(),
+                vec![glob_regex("ui/**")?, glob_regex("hud_*")?],
+            ),
+            ("icons".to_owned(), vec![glob_regex("**/icon_*")?]),
+        ];
+        assert_eq!(assign_file("ui/icon_save.png", &rules), "ui");
+        assert_eq!(assign_file("hud_health.png", &rules), "ui");
+        assert_eq!(assign_file("fx/icon_fire.png", &rules), "icons");
+        assert_eq!(assign_file("fx/smoke/1.png", &rules), "fx");
+        assert_eq!(assign_file("player.png", &rules), "");
+        Ok(())
+    }
+
+    #[test]
+    fn index_lists_every_group() {
+        let entries = [IndexEntry {
+            name: "ui".to_owned(),
+            sheets: vec![Sheet {
+                image: "out/atlas_ui.png".to_owned(),
+                data: "out/atlas_ui.json".to_owned(),
+                scale: 1.0,
+                width: 64,
+                height: 32,
+            }],
+            sprites: vec!["ok button".to_owned()],
+        }];
+        assert_eq!(
+            format_index(MetaDataFormat::Json, &entries),
+            "{\"groups\":[{\"name\":\"ui\",\"sheets\":[{\"data\":\"atlas_ui.json\",\"height\":32,\"image\":\"atlas_ui.png\",\"scale\":1,\"width\":64}],\"sprites\":[\"ok_button\"]}]}\n"
+        );
+        let lua = format_index(MetaDataFormat::Lua, &entries);
+        assert!(lua.starts_with("return {\n    {\n        name = \"ui\",\n"));
+        assert!(lua.contains("{ image = \"atlas_ui.png\", data = \"atlas_ui.json\", scale = 1, width = 64, height = 32 },"));
+        assert!(lua.contains("sprites = { \"OK_BUTTON\" },"));
+    }
+}
@@ -1,17 +1,69 @@
-#![feature(path_file_prefix)]
-
 use std::{env, error::Error, process};
 
+mod alpha;
+mod animation;
+mod aseprite;
+mod atlas;
+mod block_compression;
 mod bounding_box;
 mod config;
+mod debug_image;
+mod gpu_texture;
+mod grid;
+mod group;
+mod meta_data;
+mod nine_slice;
+mod optimize;
 mod packer;
+mod palette;
+mod pivot;
+mod preview;
+mod report;
+mod sidecar;
+mod stable;
 mod tree2d;
+mod unpack;
+mod verify;
 
 use config::Config;
 
 fn main() -> Result<(), Box<dyn Error>> {
     let args: Vec<String> = env::args().collect();
 
+    if args.get(1).map(|arg| arg.as_str()) == Some("unpack") {
+        if args.len() != 5 {
+            println!("Usage: `corral unpack sheet.png sheet.json|.lua|.atlas out_dir`");
+            process::exit(1);
+        }
+        return unpack::run(&args[2], &args[3], &args[4]);
+    }
+
+    if args.get(1).map(|arg| arg.as_str()) == Some("verify") {
+        // sources are cut into sprites with the options the sheet was packed with
+        let (flags, files): (Vec<&String>, Vec<&String>) =
+            args[2..].iter().partition(|arg| arg.starts_with("--"));
+        let usage = || {
+            println!("Usage: `corral verify sheet.png sheet.json|.lua|.atlas [input_dir] [--split-layers] [--grid-names]`");
+            process::exit(1);
+        };
+        let mut options = packer::ImportOptions::default();
+        for flag in flags {
+            match flag.as_str() {
+                "--split-layers" => options.split_layers = true,
+                "--grid-names" => options.grid_names = true,
+                _ => usage(),
+            }
+        }
+        if files.len() != 2 && files.len() != 3 {
+            usage();
+        }
+        let source_dir = files.get(2).map(|dir| dir.as_str());
+        if !verify::run(files[0], files[1], source_dir, options)? {
+            process::exit(1);
+        }
+        return Ok(());
+    }
+
     let config = Config::parse(&args).unwrap_or_else(|err| {
         println!("Problem parsing arguments: {err}");
         process::exit(1);
@@ -0,0 +1,446 @@
+use serde::Deserialize;
+
+use crate::animation::Animation;
+use crate::bounding_box::BoundingBox;
+use crate::config::MetaDataFormat;
+
+// in pixels from the top left of the sprite
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pivot {
+    pub x: f32,
+    pub y: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+pub struct Borders {
+    pub left: u32,
+    pub top: u32,
+    pub right: u32,
+    pub bottom: u32,
+}
+
+#[derive(Default)]
+pub struct SpriteData {
+    pub name: String,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    // turned 90 degrees clockwise in the sheet, width and height stay the
+    // size of the sprite before it was turned
+    pub rotated: bool,
+    pub pivot: Option<Pivot>,
+    pub borders: Option<Borders>,
+}
+
+impl SpriteData {
+    // the area the sprite covers in the sheet
+    pub fn bounding_box(&self) -> BoundingBox {
+        let (width, height) = match self.rotated {
+            true => (self.height, self.width),
+            false => (self.width, self.height),
+        };
+        BoundingBox {
+            x: self.x,
+            y: self.y,
+            width,
+            height,
+        }
+    }
+
+    // the pivot in pixels and as a fraction of the sprite's size
+    fn pivot_fields(&self) -> Option<[(&'static str, f32); 4]> {
+        let pivot = self.pivot?;
+        let fraction = |v: f32, size: u32| if size == 0 { 0.0 } else { v / size as f32 };
+        Some([
+            ("x", rounded(pivot.x)),
+            ("y", rounded(pivot.y)),
+            ("normalized_x", rounded(fraction(pivot.x, self.width))),
+            ("normalized_y", rounded(fraction(pivot.y, self.height))),
+        ])
+    }
+
+    fn to_lua_string(&self) -> String {
+        let mut fields = vec![
+            format!("x = {}", self.x),
+            format!("y = {}", self.y),
+            format!("width = {}", self.width),
+            format!("height = {}", self.height),
+        ];
+        if self.rotated {
+            fields.push("rotated = true".to_owned());
+        }
+        if let Some(pivot) = self.pivot_fields() {
+            let pivot = pivot
+                .iter()
+                .map(|(name, value)| format!("{name} = {value}"))
+                .collect::<Vec<String>>()
+                .join(", ");
+            fields.push(format!("pivot = {{ {pivot} }}"));
+        }
+        if let Some(borders) = self.borders {
+            fields.push(format!(
+                "borders = {{ left = {}, top = {}, right = {}, bottom = {} }}",
+                borders.left, borders.top, borders.right, borders.bottom
+            ));
+        }
+        let fields: String = fields
+            .iter()
+            .map(|field| format!("        {field},\n"))
+            .collect();
+        format!(
+            "    {name} = {{\n{fields}    }}",
+            name = lua_name(&self.name)
+        )
+    }
+
+    fn to_json_string(&self) -> String {
+        let mut fields = vec![];
+        if let Some(borders) = self.borders {
+            fields.push(format!(
+                "\"borders\":{{\"bottom\":{},\"left\":{},\"right\":{},\"top\":{}}}",
+                borders.bottom, borders.left, borders.right, borders.top
+            ));
+        }
+        fields.push(format!("\"height\":{}", self.height));
+        fields.push(format!("\"name\":\"{}\"", json_name(&self.name)));
+        if let Some(mut pivot) = self.pivot_fields() {
+            pivot.sort_by_key(|(name, _)| *name);
+            let pivot = pivot
+                .iter()
+                .map(|(name, value)| format!("\"{name}\":{value}"))
+                .collect::<Vec<String>>()
+                .join(",");
+            fields.push(format!("\"pivot\":{{{pivot}}}"));
+        }
+        if self.rotated {
+            fields.push("\"rotated\":true".to_owned());
+        }
+        fields.push(format!("\"width\":{}", self.width));
+        fields.push(format!("\"x\":{}", self.x));
+        fields.push(format!("\"y\":{}", self.y));
+        "{".to_owned() + &fields.join(",") + "}"
+    }
+}
+
+// four decimals keep the output short and stable
+fn rounded(value: f32) -> f32 {
+    (value * 10000.0).round() / 10000.0
+}
+
+pub fn lua_name(name: &str) -> String {
+    name.replace(' ', "_").to_uppercase()
+}
+
+pub fn json_name(name: &str) -> String {
+    name.replace(' ', "_")
+}
+
+fn join_numbers(numbers: &[u32]) -> String {
+    numbers
+        .iter()
+        .map(|n| n.to_string())
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+impl Animation {
+    fn to_lua_string(&self) -> String {
+        let frames = self
+            .frames
+            .iter()
+            .map(|frame| format!("\"{}\"", lua_name(frame)))
+            .collect::<Vec<String>>()
+            .join(", ");
+        let mut fields = vec![format!("            frames = {{ {frames} }}")];
+        if !self.durations.is_empty() {
+            fields.push(format!(
+                "            durations = {{ {} }}",
+                join_numbers(&self.durations).replace(',', ", ")
+            ));
+        }
+        if let Some(direction) = self.direction {
+            fields.push(format!("            direction = \"{direction}\""));
+        }
+        format!(
+            "        {name} = {{\n{fields},\n        }}",
+            name = lua_name(&self.name),
+            fields = fields.join(",\n")
+        )
+    }
+
+    fn to_json_string(&self) -> String {
+        let frames = self
+            .frames
+            .iter()
+            .map(|frame| format!("\"{}\"", json_name(frame)))
+            .collect::<Vec<String>>()
+            .join(",");
+        let mut fields = vec![];
+        if let Some(direction) = self.direction {
+            fields.push(format!("\"direction\":\"{direction}\""));
+        }
+        if !self.durations.is_empty() {
+            fields.push(format!("\"durations\":[{}]", join_numbers(&self.durations)));
+        }
+        fields.push(format!("\"frames\":[{frames}]"));
+        format!(
+            "\"{name}\":{{{fields}}}",
+            name = json_name(&self.name),
+            fields = fields.join(",")
+        )
+    }
+}
+
+#[derive(Default)]
+pub struct SheetData {
+    pub width: u32,
+    pub height: u32,
+    pub padding: u32,
+    // the size and padding are only written when asked for with --meta
+    pub record_layout: bool,
+    pub premultiplied_alpha: bool,
+    pub animations: Vec<Animation>,
+}
+
+impl SheetData {
+    fn is_empty(&self) -> bool {
+        !self.record_layout && !self.premultiplied_alpha && self.animations.is_empty()
+    }
+
+    fn to_lua_strings(&self) -> Vec<String> {
+        let mut result = vec![];
+        if !self.animations.is_empty() {
+            let animations = self
+                .animations
+                .iter()
+                .map(|anim| anim.to_lua_string())
+                .collect::<Vec<String>>()
+                .join(",\n");
+            result.push(format!("    animations = {{\n{animations},\n    }}"));
+        }
+        let mut meta = vec![];
+        if self.record_layout {
+            meta.push(format!("        width = {},\n", self.width));
+            meta.push(format!("        height = {},\n", self.height));
+            meta.push(format!("        padding = {},\n", self.padding));
+        }
+        if self.premultiplied_alpha {
+            meta.push("        premultiplied_alpha = true,\n".to_owned());
+        }
+        if !meta.is_empty() {
+            result.push(format!("    meta = {{\n{}    }}", meta.concat()));
+        }
+        result
+    }
+
+    // top level fields of the json object, other than the sprites
+    fn to_json_strings(&self) -> Vec<String> {
+        let mut result = vec![];
+        if !self.animations.is_empty() {
+            let animations = self
+                .animations
+                .iter()
+                .map(|anim| anim.to_json_string())
+                .collect::<Vec<String>>()
+                .join(",");
+            result.push(format!("\"animations\":{{{animations}}}"));
+        }
+        let mut meta = vec![];
+        if self.record_layout {
+            meta.push(format!("\"height\":{}", self.height));
+            meta.push(format!("\"padding\":{}", self.padding));
+        }
+        if self.premultiplied_alpha {
+            meta.push("\"premultiplied_alpha\":true".to_owned());
+        }
+        if self.record_layout {
+            meta.push(format!("\"width\":{}", self.width));
+        }
+        if !meta.is_empty() {
+            result.push(format!("\"meta\":{{{}}}", meta.join(",")));
+        }
+        result
+    }
+}
+
+pub fn format(
+    module_name: &str,
+    format: MetaDataFormat,
+    sprite_data: &[SpriteData],
+    sheet_data: &SheetData,
+) -> String {
+    match format {
+        MetaDataFormat::Json => {
+            let json_string: String = sprite_data
+                .iter()
+                .map(|sd| sd.to_json_string())
+                .collect::<Vec<String>>()
+                .join(",");
+            if sheet_data.is_empty() {
+                "[".to_owned() + &json_string + "]\n"
+            } else {
+                format!(
+                    "{{{sheet},\"sprites\":[{json_string}]}}\n",
+                    sheet = sheet_data.to_json_strings().join(",")
+                )
+            }
+        }
+        MetaDataFormat::Lua => {
+            let lua_string: String = sprite_data
+                .iter()
+                .map(|sd| sd.to_lua_string())
+                .chain(sheet_data.to_lua_strings())
+                .collect::<Vec<String>>()
+                .join(",\n");
+            format!("local {fname} = {{\n", fname = module_name)
+                + &lua_string
+                + &format!("\n}}\n\nreturn {fname}\n", fname = module_name)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sprite() -> SpriteData {
+        SpriteData {
+            name: "red pixel".to_owned(),
+            x: 1,
+            y: 2,
+            width: 3,
+            height: 4,
+            ..Default::default()
+        }
+    }
+
+    fn sheet() -> SheetData {
+        SheetData {
+            width: 8,
+            height: 16,
+            padding: 2,
+            record_layout: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn json_without_sheet_data_is_an_array() {
+        let sheet_data = SheetData {
+            record_layout: false,
+            ..sheet()
+        };
+        assert_eq!(
+            format("out", MetaDataFormat::Json, &[sprite()], &sheet_data),
+            "[{\"height\":4,\"name\":\"red_pixel\",\"width\":3,\"x\":1,\"y\":2}]\n"
+        );
+        assert_eq!(
+            format("out", MetaDataFormat::Lua, &[sprite()], &sheet_data),
+            "local out = {\n    RED_PIXEL = {\n        x = 1,\n        y = 2,\n        width = 3,\n        height = 4,\n    }\n}\n\nreturn out\n"
+        );
+    }
+
+    #[test]
+    fn json_with_sheet_data() {
+        assert_eq!(
+            format("out", MetaDataFormat::Json, &[sprite()], &sheet()),
+            "{\"meta\":{\"height\":16,\"padding\":2,\"width\":8},\"sprites\":[{\"height\":4,\"name\":\"red_pixel\",\"width\":3,\"x\":1,\"y\":2}]}\n"
+        );
+        let sheet_data = SheetData {
+            premultiplied_alpha: true,
+            ..sheet()
+        };
+        assert!(
+            format("out", MetaDataFormat::Json, &[sprite()], &sheet_data).starts_with(
+                "{\"meta\":{\"height\":16,\"padding\":2,\"premultiplied_alpha\":true,\"width\":8},"
+            )
+        );
+    }
+
+    #[test]
+    fn lua_with_sheet_data() {
+        let sheet_data = SheetData {
+            premultiplied_alpha: true,
+            ..sheet()
+        };
+        let lua = format("out", MetaDataFormat::Lua, &[sprite()], &sheet_data);
+        assert!(lua.starts_with("local out = {\n    RED_PIXEL = {"));
+        assert!(lua.ends_with(
+            "    },\n    meta = {\n        width = 8,\n        height = 16,\n        padding = 2,\n        premultiplied_alpha = true,\n    }\n}\n\nreturn out\n"
+        ));
+    }
+
+    #[test]
+    fn rotated_sprite() {
+        let sprite = SpriteData {
+            rotated: true,
+            ..sprite()
+        };
+        assert_eq!(
+            sprite.to_json_string(),
+            "{\"height\":4,\"name\":\"red_pixel\",\"rotated\":true,\"width\":3,\"x\":1,\"y\":2}"
+        );
+        assert!(sprite
+            .to_lua_string()
+            .ends_with("        height = 4,\n        rotated = true,\n    }"));
+        assert_eq!(
+            (sprite.bounding_box().width, sprite.bounding_box().height),
+            (4, 3)
+        );
+    }
+
+    #[test]
+    fn pivot_and_borders() {
+        let sprite = SpriteData {
+            pivot: Some(Pivot { x: 1.0, y: -2.0 }),
+            borders: Some(Borders {
+                left: 1,
+                top: 2,
+                right: 3,
+                bottom: 4,
+            }),
+            ..sprite()
+        };
+        assert_eq!(
+            sprite.to_json_string(),
+            "{\"borders\":{\"bottom\":4,\"left\":1,\"right\":3,\"top\":2},\"height\":4,\"name\":\"red_pixel\",\"pivot\":{\"normalized_x\":0.3333,\"normalized_y\":-0.5,\"x\":1,\"y\":-2},\"width\":3,\"x\":1,\"y\":2}"
+        );
+        assert!(sprite.to_lua_string().ends_with(
+            "        pivot = { x = 1, y = -2, normalized_x = 0.3333, normalized_y = -0.5 },\n        borders = { left = 1, top = 2, right = 3, bottom = 4 },\n    }"
+        ));
+    }
+
+    fn run_animation() -> SheetData {
+        SheetData {
+            animations: vec![Animation {
+                name: "run".to_owned(),
+                frames: vec!["run 0".to_owned(), "run 1".to_owned()],
+                durations: vec![100, 80],
+                direction: None,
+            }],
+            ..sheet()
+        }
+    }
+
+    #[test]
+    fn json_with_animations() {
+        assert_eq!(
+            format("out", MetaDataFormat::Json, &[sprite()], &run_animation()),
+            "{\"animations\":{\"run\":{\"durations\":[100,80],\"frames\":[\"run_0\",\"run_1\"]}},\"meta\":{\"height\":16,\"padding\":2,\"width\":8},\"sprites\":[{\"height\":4,\"name\":\"red_pixel\",\"width\":3,\"x\":1,\"y\":2}]}\n"
+        );
+    }
+
+    #[test]
+    fn lua_with_animations() {
+        let lua = format("out", MetaDataFormat::Lua, &[sprite()], &run_animation());
+        assert!(lua.contains(
+            "    animations = {
+        RUN = {
+            frames = { \"RUN_0\", \"RUN_1\" },
+            durations = { 100, 80 },
+        },
+    }"
+        ));
+    }
+}
@@ -0,0 +1,269 @@
+use std::error::Error;
+use std::path::Path;
+
+use image::{DynamicImage, GenericImageView, ImageBuffer, Pixel, Primitive, Rgba};
+
+use crate::meta_data::{Borders, SpriteData};
+use crate::sidecar;
+
+const GUIDE: Rgba<u8> = Rgba([0, 0, 0, 255]);
+// android marks optical bounds with red along the right and bottom guides
+const OPTICAL_BOUNDS: Rgba<u8> = Rgba([255, 0, 0, 255]);
+
+// android nine patches are named like button.9.png
+pub fn is_nine_patch(path: &Path) -> bool {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    name.to_ascii_lowercase().ends_with(".9.png")
+}
+
+// the first and last pixels of the run of guide pixels along the top or left
+// of the 1px border. Android allows several stretched runs, borders only
+// describe one
+fn stretch(
+    side: &str,
+    guides: impl Iterator<Item = Rgba<u8>>,
+) -> Result<Option<(u32, u32)>, String> {
+    let mut runs: Vec<(u32, u32)> = vec![];
+    for (i, pixel) in guides.enumerate() {
+        let i = i as u32;
+        if pixel == GUIDE {
+            match runs.last_mut() {
+                Some((_, last)) if *last + 1 == i => *last = i,
+                _ => runs.push((i, i)),
+            }
+        } else if pixel[3] != 0 {
+            return Err(format!(
+                "the {side} guide pixels must be black or transparent"
+            ));
+        }
+    }
+    match runs.as_slice() {
+        [] => Ok(None),
+        [run] => Ok(Some(*run)),
+        runs => Err(format!(
+            "the {side} guide marks {} separate stretched areas, borders can only describe one",
+            runs.len()
+        )),
+    }
+}
+
+// the content area and optical bounds along the right and bottom
+fn check_content(side: &str, mut guides: impl Iterator<Item = Rgba<u8>>) -> Result<(), String> {
+    if guides.all(|pixel| pixel == GUIDE || pixel == OPTICAL_BOUNDS || pixel[3] == 0) {
+        Ok(())
+    } else {
+        Err(format!(
+            "the {side} guide pixels must be black, red or transparent"
+        ))
+    }
+}
+
+// the top and left guides mark the stretched centre, the right and bottom
+// guides mark the content area and optical bounds, which aren't written
+pub fn strip_guides(img: &DynamicImage) -> Result<(DynamicImage, Borders), String> {
+    let (width, height) = img.dimensions();
+    if width < 3 || height < 3 {
+        return Err("a nine patch needs a 1px guide border around the image".to_owned());
+    }
+    let (inner_width, inner_height) = (width - 2, height - 2);
+    let top = stretch("top", (1..width - 1).map(|x| img.get_pixel(x, 0)))?;
+    let left = stretch("left", (1..height - 1).map(|y| img.get_pixel(0, y)))?;
+    check_content(
+        "bottom",
+        (1..width - 1).map(|x| img.get_pixel(x, height - 1)),
+    )?;
+    check_content(
+        "right",
+        (1..height - 1).map(|y| img.get_pixel(width - 1, y)),
+    )?;
+
+    let ((x_first, x_last), (y_first, y_last)) = match (top, left) {
+        (Some(top), Some(left)) => (top, left),
+        _ => return Err("no stretch guides along the top and left".to_owned()),
+    };
+    let borders = Borders {
+        left: x_first,
+        top: y_first,
+        right: inner_width - 1 - x_last,
+        bottom: inner_height - 1 - y_last,
+    };
+    Ok((img.crop_imm(1, 1, inner_width, inner_height), borders))
+}
+
+pub fn from_sidecar(path: &Path) -> Result<Option<Borders>, Box<dyn Error>> {
+    sidecar::path_for(path, "borders")
+        .map(|sidecar| sidecar::read(&sidecar))
+        .transpose()
+}
+
+// the insets can't overlap or there's no centre left to stretch
+pub fn fits(borders: &Borders, width: u32, height: u32) -> bool {
+    borders.left as u64 + borders.right as u64 <= width as u64
+        && borders.top as u64 + borders.bottom as u64 <= height as u64
+}
+
+// copies the outermost pixels of each nine-slice sprite `amount` pixels out into
+// its padding, so filtering the stretched edges samples the sprite rather than
+// the empty padding or a neighbour
+pub fn extrude(img: &mut DynamicImage, sprites: &[SpriteData], amount: u32) {
+    match img {
+        DynamicImage::ImageRgba8(buf) => extrude_buffer(buf, sprites, amount),
+        DynamicImage::ImageRgba16(buf) => extrude_buffer(buf, sprites, amount),
+        DynamicImage::ImageRgba32F(buf) => extrude_buffer(buf, sprites, amount),
+        _ => (),
+    }
+}
+
+fn extrude_buffer<T: Primitive>(
+    buf: &mut ImageBuffer<Rgba<T>, Vec<T>>,
+    sprites: &[SpriteData],
+    amount: u32,
+) where
+    Rgba<T>: Pixel<Subpixel = T>,
+{
+    let (width, height) = buf.dimensions();
+    for sprite in sprites.iter().filter(|sprite| sprite.borders.is_some()) {
+        let bb = sprite.bounding_box();
+        if bb.width == 0 || bb.height == 0 {
+            continue;
+        }
+        let (right, bottom) = (bb.x + bb.width, bb.y + bb.height);
+        let (out_right, out_bottom) = ((right + amount).min(width), (bottom + amount).min(height));
+        for y in bb.y.saturating_sub(amount)..out_bottom {
+            for x in bb.x.saturating_sub(amount)..out_right {
+                let (edge_x, edge_y) = (x.clamp(bb.x, right - 1), y.clamp(bb.y, bottom - 1));
+                if (edge_x, edge_y) != (x, y) {
+                    let pixel = *buf.get_pixel(edge_x, edge_y);
+                    buf.put_pixel(x, y, pixel);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbaImage;
+
+    // a 6x5 image inside a guide border, stretching columns 2..=3 and row 1
+    fn nine_patch() -> RgbaImage {
+        let mut img = RgbaImage::from_pixel(8, 7, Rgba([200, 100, 50, 255]));
+        for x in 0..8 {
+            img.put_pixel(x, 0, Rgba([0, 0, 0, 0]));
+            img.put_pixel(x, 6, Rgba([0, 0, 0, 0]));
+        }
+        for y in 0..7 {
+            img.put_pixel(0, y, Rgba([0, 0, 0, 0]));
+            img.put_pixel(7, y, Rgba([0, 0, 0, 0]));
+        }
+        img.put_pixel(3, 0, GUIDE);
+        img.put_pixel(4, 0, GUIDE);
+        img.put_pixel(0, 2, GUIDE);
+        img
+    }
+
+    #[test]
+    fn guides_become_borders_and_are_stripped() {
+        let (img, borders) = strip_guides(&DynamicImage::ImageRgba8(nine_patch())).unwrap();
+        assert_eq!(img.dimensions(), (6, 5));
+        assert_eq!(img.get_pixel(0, 0), Rgba([200, 100, 50, 255]));
+        assert_eq!(
+            borders,
+            Borders {
+                left: 2,
+                top: 1,
+                right: 2,
+                bottom: 3,
+            }
+        );
+        assert!(fits(&borders, 6, 5));
+        assert!(!fits(&borders, 3, 5));
+    }
+
+    #[test]
+    fn rejects_bad_guides() {
+        let mut img = nine_patch();
+        img.put_pixel(5, 0, Rgba([255, 0, 0, 255]));
+        assert!(strip_guides(&DynamicImage::ImageRgba8(img)).is_err());
+
+        let mut img = nine_patch();
+        img.put_pixel(0, 2, Rgba([0, 0, 0, 0]));
+        assert!(strip_guides(&DynamicImage::ImageRgba8(img)).is_err());
+
+        // a second stretched run along the top can't be written as borders
+        let mut img = nine_patch();
+        img.put_pixel(6, 0, GUIDE);
+        assert_eq!(
+            strip_guides(&DynamicImage::ImageRgba8(img))
+                .err()
+                .as_deref(),
+            Some("the top guide marks 2 separate stretched areas, borders can only describe one")
+        );
+    }
+
+    #[test]
+    fn content_and_optical_bounds_guides_are_allowed() {
+        let mut img = nine_patch();
+        for x in 2..6 {
+            img.put_pixel(x, 6, GUIDE);
+        }
+        img.put_pixel(1, 6, OPTICAL_BOUNDS);
+        img.put_pixel(7, 1, OPTICAL_BOUNDS);
+        img.put_pixel(7, 3, GUIDE);
+        let (_, borders) = strip_guides(&DynamicImage::ImageRgba8(img)).unwrap();
+        assert_eq!((borders.left, borders.top), (2, 1));
+
+        let mut img = nine_patch();
+        img.put_pixel(7, 3, Rgba([0, 0, 255, 255]));
+        assert!(strip_guides(&DynamicImage::ImageRgba8(img)).is_err());
+    }
+
+    #[test]
+    fn recognises_nine_patches() {
+        assert!(is_nine_patch(Path::new("ui/button.9.png")));
+        assert!(!is_nine_patch(Path::new("ui/button.png")));
+    }
+
+    #[test]
+    fn extrudes_nine_slice_sprites_into_their_padding() {
+        let mut sheet = RgbaImage::new(12, 6);
+        for (x, y) in [(2, 2), (3, 2), (2, 3), (3, 3)] {
+            sheet.put_pixel(x, y, Rgba([x as u8, y as u8, 0, 255]));
+            sheet.put_pixel(x + 6, y, Rgba([x as u8, y as u8, 0, 255]));
+        }
+        let sprite = |x: u32, borders: Option<Borders>| SpriteData {
+            name: String::new(),
+            x,
+            y: 2,
+            width: 2,
+            height: 2,
+            rotated: false,
+            pivot: None,
+            borders,
+        };
+        let borders = Borders {
+            left: 1,
+            top: 1,
+            right: 1,
+            bottom: 1,
+        };
+        let mut img = DynamicImage::ImageRgba8(sheet.clone());
+        extrude(&mut img, &[sprite(2, Some(borders)), sprite(8, None)], 1);
+        let img = img.to_rgba8();
+
+        assert_eq!(*img.get_pixel(1, 1), Rgba([2, 2, 0, 255]));
+        assert_eq!(*img.get_pixel(4, 2), Rgba([3, 2, 0, 255]));
+        assert_eq!(*img.get_pixel(3, 4), Rgba([3, 3, 0, 255]));
+        assert_eq!(*img.get_pixel(4, 4), Rgba([3, 3, 0, 255]));
+        assert_eq!(*img.get_pixel(0, 0), Rgba([0, 0, 0, 0]));
+        assert_eq!(*img.get_pixel(5, 2), Rgba([0, 0, 0, 0]));
+        // sprites without borders are left alone
+        assert_eq!(*img.get_pixel(7, 2), Rgba([0, 0, 0, 0]));
+        for (x, y, pixel) in sheet.enumerate_pixels() {
+            if pixel[3] > 0 {
+                assert_eq!(img.get_pixel(x, y), pixel);
+            }
+        }
+    }
+}
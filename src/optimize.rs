@@ -0,0 +1,187 @@
+use std::time::{Duration, Instant};
+
+pub struct Settings {
+    pub seed: u64,
+    pub time_budget: Option<Duration>,
+    pub iterations: Option<u64>,
+    pub target_occupancy: Option<f64>,
+    pub allow_rotation: bool,
+}
+
+pub struct Outcome {
+    pub order: Vec<usize>,
+    pub rotated: Vec<bool>,
+    pub cost: u64,
+    pub iterations: u64,
+}
+
+// splitmix64, small and the same on every platform so a seed always gives the
+// same search
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn unit(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+// swaps two entries, moves one entry, reverses a run of entries or, when
+// sprites may be rotated, turns one of them. `rotated` is indexed by sprite
+// rather than by position in the order, and is empty when rotation is off
+fn mutate(order: &mut [usize], rotated: &mut [bool], rng: &mut Rng) {
+    let (a, b) = (rng.below(order.len()), rng.below(order.len()));
+    let (low, high) = (a.min(b), a.max(b));
+    let moves = if rotated.is_empty() { 3 } else { 4 };
+    match rng.below(moves) {
+        0 => order.swap(a, b),
+        1 if a < b => order[a..=b].rotate_left(1),
+        1 => order[b..=a].rotate_right(1),
+        2 => order[low..=high].reverse(),
+        _ => rotated[order[a]] = !rotated[order[a]],
+    }
+}
+
+// simulated annealing over the order `cost` is evaluated in and which sprites
+// are rotated, `cost` returns None for arrangements that can't be packed.
+// Cooling follows the iteration count rather than the clock, so with the same
+// seed and an iteration limit the search is reproducible
+pub fn anneal<F>(
+    initial: Vec<usize>,
+    rotated: Vec<bool>,
+    cost: F,
+    used_area: u64,
+    settings: &Settings,
+) -> Outcome
+where
+    F: Fn(&[usize], &[bool]) -> Option<u64>,
+{
+    let start = Instant::now();
+    let mut rng = Rng(settings.seed);
+    let mut current = (initial, rotated);
+    let Some(mut current_cost) = cost(&current.0, &current.1) else {
+        return Outcome {
+            order: current.0,
+            rotated: current.1,
+            cost: u64::MAX,
+            iterations: 0,
+        };
+    };
+    let mut best = current.clone();
+    let mut best_cost = current_cost;
+    let initial_temperature = current_cost as f64 * 0.01;
+
+    let mut iterations = 0;
+    loop {
+        let reached_target = settings
+            .target_occupancy
+            .is_some_and(|target| used_area as f64 >= target * best_cost as f64);
+        let out_of_time = settings
+            .time_budget
+            .is_some_and(|budget| start.elapsed() >= budget);
+        let out_of_iterations = settings.iterations.is_some_and(|limit| iterations >= limit);
+        let fixed = current.0.len() < 2 && current.1.is_empty();
+        if fixed || reached_target || out_of_time || out_of_iterations {
+            break;
+        }
+        iterations += 1;
+
+        let mut candidate = current.clone();
+        mutate(&mut candidate.0, &mut candidate.1, &mut rng);
+        let Some(candidate_cost) = cost(&candidate.0, &candidate.1) else {
+            continue;
+        };
+        let temperature = initial_temperature / (1.0 + iterations as f64 / 64.0);
+        let worse_by = candidate_cost as f64 - current_cost as f64;
+        if worse_by <= 0.0 || rng.unit() < (-worse_by / temperature).exp() {
+            current = candidate;
+            current_cost = candidate_cost;
+            if current_cost < best_cost {
+                best = current.clone();
+                best_cost = current_cost;
+            }
+        }
+    }
+
+    Outcome {
+        order: best.0,
+        rotated: best.1,
+        cost: best_cost,
+        iterations,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the number of pairs out of order, plus one so a sorted order still costs something
+    fn inversions(order: &[usize]) -> Option<u64> {
+        let mut count = 1;
+        for i in 0..order.len() {
+            for j in i + 1..order.len() {
+                if order[i] > order[j] {
+                    count += 1;
+                }
+            }
+        }
+        Some(count)
+    }
+
+    fn settings(seed: u64) -> Settings {
+        Settings {
+            seed,
+            time_budget: None,
+            iterations: Some(2000),
+            target_occupancy: None,
+            allow_rotation: false,
+        }
+    }
+
+    #[test]
+    fn anneal_improves_and_is_reproducible() {
+        let initial: Vec<usize> = (0..12).rev().collect();
+        let cost = |order: &[usize], _: &[bool]| inversions(order);
+        let first = anneal(initial.clone(), vec![], cost, 1, &settings(7));
+        assert!(first.cost < inversions(&initial).unwrap());
+        assert_eq!(first.iterations, 2000);
+        assert!(first.rotated.is_empty());
+
+        let second = anneal(initial, vec![], cost, 1, &settings(7));
+        assert_eq!(first.order, second.order);
+    }
+
+    #[test]
+    fn anneal_turns_sprites() {
+        // every sprite left unrotated costs one more
+        let cost = |_: &[usize], rotated: &[bool]| {
+            Some(1 + rotated.iter().filter(|rotated| !**rotated).count() as u64)
+        };
+        let outcome = anneal((0..4).collect(), vec![false; 4], cost, 1, &settings(7));
+        assert_eq!(outcome.rotated, vec![true; 4]);
+        assert_eq!(outcome.cost, 1);
+    }
+
+    #[test]
+    fn anneal_stops_at_target() {
+        let initial: Vec<usize> = (0..12).rev().collect();
+        let settings = Settings {
+            target_occupancy: Some(0.01),
+            ..settings(7)
+        };
+        // 1 / 67 is already above 1%
+        let outcome = anneal(initial, vec![], |order, _| inversions(order), 1, &settings);
+        assert_eq!(outcome.iterations, 0);
+    }
+}
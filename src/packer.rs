@@ -1,91 +1,242 @@
+use std::collections::{BTreeMap, HashMap};
 use std::error::Error;
 use std::fs;
-use std::io::Write;
+use std::io::{BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
 
-use crate::config::Config;
-use crate::config::MetaDataFormat;
-use crate::tree2d::{DataSize, Tree2d};
-use image::{DynamicImage, ImageEncoder};
+use crate::alpha;
+use crate::animation::{self, Animation};
+use crate::aseprite;
+use crate::atlas::{self, Atlas, Region, Rotation};
+use crate::bounding_box::BoundingBox;
+use crate::config::{self, Config};
+use crate::config::{ImageFormat, MetaDataFormat, SortKey};
+use crate::debug_image::{self, Layout};
+use crate::gpu_texture;
+use crate::grid;
+use crate::group::{self, IndexEntry, Sheet};
+use crate::meta_data::{self, Borders, Pivot, SheetData, SpriteData};
+use crate::nine_slice;
+use crate::optimize;
+use crate::palette;
+use crate::pivot;
+use crate::preview;
+use crate::report::{Report, Timings};
+use crate::sidecar;
+use crate::stable;
+use crate::tree2d::{DataSize, LeafChoice, Tree2d};
+use image::{
+    imageops::FilterType, ColorType, DynamicImage, ImageBuffer, ImageEncoder, Pixel, Primitive,
+    Rgba,
+};
+use rayon::prelude::*;
 
 struct NamedDynamicImage {
     name: String,
     img: DynamicImage,
+    pivot: Option<Pivot>,
+    borders: Option<Borders>,
+}
+
+// what decides how one file is cut into sprites
+#[derive(Clone, Copy, Default)]
+pub struct ImportOptions {
+    pub split_layers: bool,
+    pub grid_names: bool,
+}
+
+impl NamedDynamicImage {
+    fn new(name: String, img: DynamicImage) -> NamedDynamicImage {
+        NamedDynamicImage {
+            name,
+            img,
+            pivot: None,
+            borders: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum BitDepth {
+    Eight,
+    Sixteen,
+    Float,
+}
+
+impl BitDepth {
+    fn of(img: &DynamicImage) -> BitDepth {
+        match img.color() {
+            ColorType::Rgb32F | ColorType::Rgba32F => BitDepth::Float,
+            ColorType::L16 | ColorType::La16 | ColorType::Rgb16 | ColorType::Rgba16 => {
+                BitDepth::Sixteen
+            }
+            _ => BitDepth::Eight,
+        }
+    }
+}
+
+impl std::fmt::Display for BitDepth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self {
+            BitDepth::Eight => write!(f, "8 bit"),
+            BitDepth::Sixteen => write!(f, "16 bit"),
+            BitDepth::Float => write!(f, "32 bit float"),
+        }
+    }
 }
 
 struct PackedImage {
     img: DynamicImage,
-    meta_data: String,
+    padding: u32,
+    // area of the tree leaves holding the sprites with their padding
+    padded_area: u64,
+    layout: Layout,
+    sprite_data: Vec<SpriteData>,
 }
 
 impl PackedImage {
+    fn rescale(
+        &self,
+        img_collection: &ImageCollection,
+        depth: BitDepth,
+        scale: f32,
+        filter: FilterType,
+    ) -> PackedImage {
+        let scaled = |v: u32| (v as f32 * scale).round() as u32;
+        let images: HashMap<&str, &DynamicImage> = img_collection
+            .named_images
+            .iter()
+            .map(|named_img| (named_img.name.as_str(), &named_img.img))
+            .collect();
+
+        let mut img = new_sheet(depth, scaled(self.img.width()), scaled(self.img.height()));
+        let mut sprite_data = vec![];
+        for sd in &self.sprite_data {
+            let scaled_sd = SpriteData {
+                name: sd.name.to_owned(),
+                x: scaled(sd.x),
+                y: scaled(sd.y),
+                width: scaled(sd.width).max(1),
+                height: scaled(sd.height).max(1),
+                rotated: sd.rotated,
+                pivot: sd.pivot.map(|pivot| Pivot {
+                    x: pivot.x * scale,
+                    y: pivot.y * scale,
+                }),
+                borders: sd.borders.map(|borders| Borders {
+                    left: scaled(borders.left),
+                    top: scaled(borders.top),
+                    right: scaled(borders.right),
+                    bottom: scaled(borders.bottom),
+                }),
+            };
+            sprite_data.push(scaled_sd);
+        }
+
+        let resized: Vec<Option<DynamicImage>> = sprite_data
+            .par_iter()
+            .map(|sd| match images.get(sd.name.as_str()) {
+                Some(sprite) if scale != 1.0 => {
+                    let resized = sprite.resize_exact(sd.width, sd.height, filter);
+                    Some(if sd.rotated {
+                        resized.rotate90()
+                    } else {
+                        resized
+                    })
+                }
+                Some(sprite) if sd.rotated => Some(sprite.rotate90()),
+                _ => None,
+            })
+            .collect();
+        let mut sprites = vec![];
+        for (sd, resized) in sprite_data.iter().zip(resized.iter()) {
+            let sprite = match resized {
+                Some(resized) => resized,
+                None => match images.get(sd.name.as_str()) {
+                    Some(sprite) => *sprite,
+                    None => continue,
+                },
+            };
+            sprites.push((sprite, sd.x, sd.y));
+        }
+        blit_all(&mut img, &sprites);
+
+        PackedImage {
+            img,
+            padding: scaled(self.padding),
+            padded_area: (self.padded_area as f64 * (scale as f64).powi(2)).round() as u64,
+            // the layout is only drawn at the scale it was packed at
+            layout: Layout::default(),
+            sprite_data,
+        }
+    }
+
+    // the sheet for one scale, its data file and lua module named after the
+    // sheet without its scale suffix
     fn write(
         &self,
-        output_file: &str,
-        output_file_format: MetaDataFormat,
+        config: &Config,
+        sheet_data: &SheetData,
+        sheet_file: &str,
+        scale_suffix: &str,
     ) -> Result<(), Box<dyn Error>> {
-        let buf = fs::File::create(output_file)?;
-        let encoder = image::codecs::png::PngEncoder::new_with_quality(
-            buf,
-            image::codecs::png::CompressionType::Best,
-            image::codecs::png::FilterType::Adaptive,
-        );
-
-        encoder.write_image(
-            self.img.as_bytes(),
-            self.img.width(),
-            self.img.height(),
-            self.img.color(),
-        )?;
+        let output_file = &suffixed_file_name(sheet_file, scale_suffix);
+        match config.image_format {
+            ImageFormat::Png if config.indexed => {
+                let sprites: Vec<(&str, BoundingBox)> = self
+                    .sprite_data
+                    .iter()
+                    .map(|sd| (sd.name.as_str(), sd.bounding_box()))
+                    .collect();
+                palette::write_indexed(
+                    output_file,
+                    &self.img.to_rgba8(),
+                    &sprites,
+                    config.palette_file.as_deref(),
+                    config.dither,
+                )?;
+            }
+            ImageFormat::Png => write_png(&self.img, fs::File::create(output_file)?)?,
+            ImageFormat::Exr => {
+                self.img
+                    .save_with_format(output_file, image::ImageFormat::OpenExr)?;
+            }
+            ImageFormat::Ktx2 | ImageFormat::Dds => gpu_texture::write(
+                output_file,
+                &self.img.to_rgba8(),
+                config.image_format,
+                config.compression,
+                config.mipmaps,
+                config.premultiply_alpha,
+            )?,
+        }
 
-        let extension = match output_file_format {
-            MetaDataFormat::Json => ".json",
-            MetaDataFormat::Lua => ".lua",
-        };
+        let module_name = sheet_file.split('.').collect::<Vec<&str>>()[0].to_owned();
+        let meta_data = meta_data::format(
+            &module_name,
+            config.output_file_format,
+            &self.sprite_data,
+            sheet_data,
+        );
 
-        let json_file = output_file.split('.').collect::<Vec<&str>>()[0].to_owned() + extension;
-        let mut buf = fs::File::create(&json_file)?;
-        match buf.write_all(self.meta_data.as_bytes()) {
+        let mut buf = fs::File::create(data_file_name(config, sheet_file, scale_suffix))?;
+        match buf.write_all(meta_data.as_bytes()) {
             Ok(..) => Ok(()),
             Err(e) => Result::Err(Box::new(e)),
         }
     }
 }
 
-struct SpriteData {
-    name: String,
-    x: u32,
-    y: u32,
-    width: u32,
-    height: u32,
-}
-
-impl SpriteData {
-    fn to_lua_string(&self) -> String {
-        std::format!(
-            "    {name} = {{
-        x = {x},
-        y = {y},
-        width = {width},
-        height = {height},
-    }}",
-            name = self.name.replace(' ', "_").to_uppercase(),
-            x = self.x,
-            y = self.y,
-            width = self.width,
-            height = self.height
-        )
-    }
-
-    fn to_json_string(&self) -> String {
-        std::format!(
-            "{{\"height\":{height},\"name\":\"{name}\",\"width\":{width},\"x\":{x},\"y\":{y}}}",
-            name = self.name.replace(' ', "_"),
-            x = self.x,
-            y = self.y,
-            width = self.width,
-            height = self.height
-        )
-    }
+// fixed encoder settings so identical sheets give identical files
+fn write_png<W: Write>(img: &DynamicImage, writer: W) -> Result<(), Box<dyn Error>> {
+    let encoder = image::codecs::png::PngEncoder::new_with_quality(
+        writer,
+        image::codecs::png::CompressionType::Best,
+        image::codecs::png::FilterType::Adaptive,
+    );
+    encoder.write_image(img.as_bytes(), img.width(), img.height(), img.color())?;
+    Ok(())
 }
 
 #[allow(dead_code)]
@@ -100,15 +251,14 @@ impl ImageCollection {
     fn new(mut named_images: Vec<NamedDynamicImage>) -> ImageCollection {
         let mut max_width = 0u32;
         let mut max_height = 0u32;
-        for NamedDynamicImage { name: _, img } in &named_images {
+        for NamedDynamicImage { img, .. } in &named_images {
             max_width = max_width.max(img.width());
             max_height = max_height.max(img.height());
         }
         let num_images = named_images.len() as u32;
 
-        named_images.sort_by(|a, b| {
-            (b.img.width() * b.img.height()).cmp(&(a.img.width() * a.img.height()))
-        });
+        // the input order never changes the packing
+        named_images.sort_by(|a, b| a.name.cmp(&b.name));
 
         ImageCollection {
             named_images,
@@ -117,121 +267,1067 @@ impl ImageCollection {
             num_images,
         }
     }
+
+    // largest first by the sort key, ties broken by height, width and then name
+    fn sorted(&self, sort_key: SortKey) -> Vec<&NamedDynamicImage> {
+        let mut sorted: Vec<&NamedDynamicImage> = self.named_images.iter().collect();
+        if sort_key != SortKey::None {
+            sorted.sort_by(|a, b| {
+                let key = |named_img: &NamedDynamicImage| {
+                    let (width, height) = (named_img.img.width(), named_img.img.height());
+                    (sort_key.key(width, height), height, width)
+                };
+                key(b).cmp(&key(a))
+            });
+        }
+        sorted
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Heuristic {
+    sort_key: SortKey,
+    leaf_choice: LeafChoice,
+}
+
+impl Heuristic {
+    fn all() -> Vec<Heuristic> {
+        SortKey::ALL
+            .into_iter()
+            .flat_map(|sort_key| {
+                LeafChoice::ALL
+                    .into_iter()
+                    .map(move |leaf_choice| Heuristic {
+                        sort_key,
+                        leaf_choice,
+                    })
+            })
+            .collect()
+    }
+}
+
+impl Default for Heuristic {
+    fn default() -> Self {
+        Heuristic {
+            sort_key: SortKey::Area,
+            leaf_choice: LeafChoice::MostSquare,
+        }
+    }
+}
+
+impl std::fmt::Display for Heuristic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "--sort={} --leaf={}", self.sort_key, self.leaf_choice)
+    }
 }
 
 pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
-    let img_collection = load_all(&config.input_dir)?;
-    let packed_img = pack(
-        &config.output_file,
-        config.output_file_format,
-        config.padding,
-        img_collection,
-    )?;
-    packed_img.write(&config.output_file, config.output_file_format)?;
+    if let Some(jobs) = config.jobs {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(jobs)
+            .build_global()?;
+    }
+    let Some(grouping) = &config.grouping else {
+        // read_dir order depends on the file system
+        let mut paths = fs::read_dir(&config.input_dir)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        paths.sort();
+        pack_sheet(&config, &paths, Path::new(&config.input_dir), "")?;
+        return Ok(());
+    };
+
+    let mut entries = vec![];
+    for group in group::assign(&config.input_dir, grouping)? {
+        let suffix = match group.name.as_str() {
+            "" => String::new(),
+            name => format!("_{name}"),
+        };
+        println!("Group {}:", group.name);
+        let (sheets, sprites) = pack_sheet(&config, &group.paths, &group.dir, &suffix)?;
+        entries.push(IndexEntry {
+            name: group.name,
+            sheets,
+            sprites,
+        });
+    }
+    let index_file = Path::new(&suffixed_file_name(&config.output_file, "_index"))
+        .with_extension(data_extension(config.output_file_format))
+        .to_string_lossy()
+        .to_string();
+    group::write_index(&index_file, config.output_file_format, &entries)?;
+    println!("Packed {} groups, listed in {index_file}", entries.len());
     Ok(())
 }
 
-fn load_all(input_dir: &str) -> Result<ImageCollection, Box<dyn Error>> {
+// packs the images at `paths` into one sheet, the output files named with `suffix`.
+// Returns the files written and the names of the sprites on the sheet
+fn pack_sheet(
+    config: &Config,
+    paths: &[PathBuf],
+    sidecar_dir: &Path,
+    suffix: &str,
+) -> Result<(Vec<Sheet>, Vec<String>), Box<dyn Error>> {
+    let output_file = suffixed_file_name(&config.output_file, suffix);
+    let mut timings = Timings::default();
+    let start = Instant::now();
+    let (img_collection, mut animations) = load_all(config, paths)?;
+    timings.decode = start.elapsed();
+    let depth = target_depth(config, &img_collection);
+
+    // frames decoded from animated files already belong to an animation
+    let sprite_names: Vec<&str> = img_collection
+        .named_images
+        .iter()
+        .map(|named_img| named_img.name.as_str())
+        .filter(|name| {
+            !animations
+                .iter()
+                .any(|anim| anim.frames.iter().any(|f| f == name))
+        })
+        .collect();
+    animations.extend(animation::detect(&config.frame_pattern, &sprite_names));
+    animations.sort_by(|a, b| a.name.cmp(&b.name));
+    animation::load_durations(sidecar_dir, &mut animations)?;
+    let mut sheet_data = SheetData {
+        record_layout: config.meta,
+        premultiplied_alpha: config.premultiply_alpha,
+        animations,
+        ..Default::default()
+    };
+
+    // pack once on a grid coarse enough that every scale lands on whole pixels
+    let grid = scale_grid(&config.scales);
+    let start = Instant::now();
+    let heuristics = if config.best {
+        Heuristic::all()
+    } else {
+        vec![Heuristic {
+            sort_key: config.sort_key,
+            leaf_choice: config.leaf_choice,
+        }]
+    };
+    let (padding, alignment) = (
+        config.padding as u32 * grid,
+        config.compression.block_dimension() * grid,
+    );
+    let sheet_size = config.sheet_size;
+    let stable_file = config
+        .stable_file
+        .as_ref()
+        .map(|stable_file| suffixed_file_name(stable_file, suffix));
+    let stable_arrangement = stable_file.as_ref().and_then(|stable_file| {
+        atlas::read(stable_file)
+            .map_err(|err| err.to_string())
+            .and_then(|previous| {
+                arrange_stable(
+                    padding,
+                    alignment,
+                    sheet_size,
+                    &img_collection.sorted(config.sort_key),
+                    &previous,
+                )
+            })
+            .map_err(|reason| {
+                println!(
+                    "Warning: can't keep the layout of {stable_file}, {reason}, repacking every sprite"
+                )
+            })
+            .ok()
+    });
+    let arrangement = match stable_arrangement {
+        Some(arrangement) => arrangement,
+        None => {
+            let (heuristic, mut arrangement) =
+                arrange_best(padding, alignment, sheet_size, &img_collection, &heuristics)?;
+            if let Some(settings) = &config.optimize {
+                arrangement = optimize_arrangement(
+                    padding,
+                    alignment,
+                    sheet_size,
+                    &img_collection,
+                    heuristic,
+                    arrangement,
+                    settings,
+                )?;
+            }
+            arrangement
+        }
+    };
+    if !arrangement.unplaced.is_empty() {
+        let unplaced = arrangement.describe_unplaced();
+        if !config.allow_partial {
+            return Err(unplaced.into());
+        }
+        println!("Warning: {unplaced}, writing the sheet without them");
+    }
+    let packed_img = render(arrangement, depth);
+    timings.pack = start.elapsed();
+
+    if let Some(debug_file) = &config.debug_image {
+        debug_image::write(
+            &suffixed_file_name(debug_file, suffix),
+            &packed_img.img.to_rgba8(),
+            &packed_img.sprite_data,
+            &packed_img.layout,
+            packed_img.padding,
+        )?;
+    }
+    if config.preview_html {
+        let output_path = Path::new(&output_file);
+        preview::write(
+            &output_path.with_extension("html").to_string_lossy(),
+            &output_path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy(),
+            &packed_img.img.to_rgba8(),
+            &packed_img.sprite_data,
+            packed_img.padding,
+        )?;
+    }
+
+    let start = Instant::now();
+
+    let mut sheets = vec![];
+    for (scale, scale_suffix) in config.scales.iter().zip(config.scale_suffixes.iter()) {
+        let mut scaled_img = packed_img.rescale(&img_collection, depth, *scale, config.filter);
+        // half the padding, so neighbours' extrusions never meet
+        nine_slice::extrude(
+            &mut scaled_img.img,
+            &scaled_img.sprite_data,
+            scaled_img.padding / 2,
+        );
+        if config.alpha_bleed {
+            alpha::bleed(&mut scaled_img.img);
+        }
+        if config.premultiply_alpha {
+            alpha::premultiply(&mut scaled_img.img);
+        }
+        sheet_data.width = scaled_img.img.width();
+        sheet_data.height = scaled_img.img.height();
+        sheet_data.padding = scaled_img.padding;
+        scaled_img.write(config, &sheet_data, &output_file, scale_suffix)?;
+        sheets.push(Sheet {
+            data: data_file_name(config, &output_file, scale_suffix),
+            image: suffixed_file_name(&output_file, scale_suffix),
+            scale: *scale,
+            width: sheet_data.width,
+            height: sheet_data.height,
+        });
+    }
+    timings.encode = start.elapsed();
+
+    let report = Report::new(
+        &packed_img.sprite_data,
+        packed_img.img.width(),
+        packed_img.img.height(),
+        packed_img.padding,
+        packed_img.padded_area,
+        timings,
+    );
+    report.print();
+    if let Some(report_file) = &config.report_file {
+        report.write(&suffixed_file_name(report_file, suffix))?;
+    }
+    let sprites = packed_img
+        .sprite_data
+        .iter()
+        .map(|sd| sd.name.to_owned())
+        .collect();
+    Ok((sheets, sprites))
+}
+
+// the least common multiple of the scales' denominators
+fn scale_grid(scales: &[f32]) -> u32 {
+    fn gcd(a: u32, b: u32) -> u32 {
+        if b == 0 {
+            a
+        } else {
+            gcd(b, a % b)
+        }
+    }
+    scales
+        .iter()
+        .filter_map(|scale| config::scale_denominator(*scale))
+        .fold(1, |grid, denominator| {
+            grid / gcd(grid, denominator) * denominator
+        })
+}
+
+fn data_extension(format: MetaDataFormat) -> &'static str {
+    match format {
+        MetaDataFormat::Json => "json",
+        MetaDataFormat::Lua => "lua",
+    }
+}
+
+// the metadata is written next to the sheet, the scale suffix follows the name
+fn data_file_name(config: &Config, output_file: &str, scale_suffix: &str) -> String {
+    let stem = output_file.split('.').collect::<Vec<&str>>()[0];
+    format!(
+        "{stem}{scale_suffix}.{}",
+        data_extension(config.output_file_format)
+    )
+}
+
+fn suffixed_file_name(output_file: &str, suffix: &str) -> String {
+    if suffix.is_empty() {
+        return output_file.to_owned();
+    }
+    let path = Path::new(output_file);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let file_name = match path.extension() {
+        Some(extension) => format!("{stem}{suffix}.{}", extension.to_string_lossy()),
+        None => format!("{stem}{suffix}"),
+    };
+    path.with_file_name(file_name).to_string_lossy().to_string()
+}
+
+fn target_depth(config: &Config, img_collection: &ImageCollection) -> BitDepth {
+    let deepest = img_collection
+        .named_images
+        .iter()
+        .map(|named_img| BitDepth::of(&named_img.img))
+        .max()
+        .unwrap_or(BitDepth::Eight);
+
+    let depth = match config.image_format {
+        ImageFormat::Exr => BitDepth::Float,
+        _ if !config.preserve_depth => return BitDepth::Eight,
+        _ if deepest == BitDepth::Float => {
+            println!(
+                "Warning: png output cannot store float data, use an .exr output file to keep it"
+            );
+            BitDepth::Sixteen
+        }
+        _ => deepest,
+    };
+
+    for named_img in &img_collection.named_images {
+        let img_depth = BitDepth::of(&named_img.img);
+        if img_depth < depth {
+            println!(
+                "Warning: {name} is {img_depth}, promoted to {depth}",
+                name = named_img.name
+            );
+        } else if img_depth > depth {
+            println!(
+                "Warning: {name} is {img_depth}, demoted to {depth}",
+                name = named_img.name
+            );
+        }
+    }
+    depth
+}
+
+// sprites never overlap, so bands of rows of the sheet are filled in parallel
+fn blit_all(img_packed: &mut DynamicImage, sprites: &[(&DynamicImage, u32, u32)]) {
+    match img_packed {
+        DynamicImage::ImageRgba8(buf) => {
+            let sprites: Vec<_> = sprites
+                .par_iter()
+                .map(|(img, x, y)| (img.to_rgba8(), *x, *y))
+                .collect();
+            blit_buffer(buf, &sprites);
+        }
+        DynamicImage::ImageRgba16(buf) => {
+            let sprites: Vec<_> = sprites
+                .par_iter()
+                .map(|(img, x, y)| (img.to_rgba16(), *x, *y))
+                .collect();
+            blit_buffer(buf, &sprites);
+        }
+        DynamicImage::ImageRgba32F(buf) => {
+            let sprites: Vec<_> = sprites
+                .par_iter()
+                .map(|(img, x, y)| (img.to_rgba32f(), *x, *y))
+                .collect();
+            blit_buffer(buf, &sprites);
+        }
+        _ => (),
+    }
+}
+
+type Buffer<T> = ImageBuffer<Rgba<T>, Vec<T>>;
+
+fn blit_buffer<T: Primitive + Send + Sync>(buf: &mut Buffer<T>, sprites: &[(Buffer<T>, u32, u32)])
+where
+    Rgba<T>: Pixel<Subpixel = T>,
+{
+    const BAND_ROWS: usize = 16;
+    let (width, height) = (buf.width() as usize, buf.height() as usize);
+    if width == 0 || height == 0 {
+        return;
+    }
+    let row_len = width * 4;
+    buf.par_chunks_mut(row_len * BAND_ROWS)
+        .enumerate()
+        .for_each(|(band, rows)| {
+            let band_start = band * BAND_ROWS;
+            let band_end = band_start + rows.len() / row_len;
+            for (sprite, x, y) in sprites {
+                let (x, y) = (*x as usize, *y as usize);
+                if x >= width {
+                    continue;
+                }
+                let sprite_width = (sprite.width() as usize).min(width - x);
+                let sprite_row_len = sprite.width() as usize * 4;
+                let start = y.max(band_start);
+                let end = (y + sprite.height() as usize).min(band_end);
+                for row in start..end {
+                    let from = (row - y) * sprite_row_len;
+                    let to = (row - band_start) * row_len + x * 4;
+                    rows[to..to + sprite_width * 4]
+                        .copy_from_slice(&sprite.as_raw()[from..from + sprite_width * 4]);
+                }
+            }
+        });
+}
+
+type Loaded = (Vec<NamedDynamicImage>, Vec<Animation>);
+
+fn load_all(
+    config: &Config,
+    paths: &[PathBuf],
+) -> Result<(ImageCollection, Vec<Animation>), Box<dyn Error>> {
     let mut images = Vec::new();
+    let mut animations = Vec::new();
+    let options = ImportOptions {
+        split_layers: config.split_layers,
+        grid_names: config.grid_names,
+    };
+
+    // files are decoded in parallel and gathered back in name order
+    let loaded: Vec<Result<Loaded, String>> = paths
+        .par_iter()
+        .map(|path| {
+            let mut images = vec![];
+            let mut animations = vec![];
+            load_file(options, path, &mut images, &mut animations)
+                .and_then(|_| apply_borders(path, &mut images))
+                .and_then(|_| apply_pivots(path, &mut images))
+                .map(|_| (images, animations))
+                .map_err(|err| err.to_string())
+        })
+        .collect();
+    // sprites are named after their file, so files with the same name in
+    // different directories of a group would overwrite each other in the data
+    let mut sources: BTreeMap<String, Vec<&PathBuf>> = BTreeMap::new();
+    for (path, result) in paths.iter().zip(loaded) {
+        let (file_images, file_animations) = result?;
+        for named_img in &file_images {
+            let files = sources
+                .entry(atlas::normalise_name(&named_img.name))
+                .or_default();
+            if !files.contains(&path) {
+                files.push(path);
+            }
+        }
+        images.extend(file_images);
+        animations.extend(file_animations);
+    }
+    let clashes: Vec<String> = sources
+        .iter()
+        .filter(|(_, files)| files.len() > 1)
+        .map(|(name, files)| {
+            let files: Vec<String> = files
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect();
+            format!("{name} from {}", files.join(", "))
+        })
+        .collect();
+    if !clashes.is_empty() {
+        return Err(format!(
+            "Sprites from different files share a name, rename or regroup the files: {}",
+            clashes.join("; ")
+        )
+        .into());
+    }
+
+    Ok((ImageCollection::new(images), animations))
+}
 
-    let paths = fs::read_dir(input_dir)?;
+// a borders sidecar wins over .9.png guides and aseprite slices
+fn apply_borders(path: &Path, images: &mut [NamedDynamicImage]) -> Result<(), Box<dyn Error>> {
+    if images.is_empty() {
+        return Ok(());
+    }
+    let Some(borders) = nine_slice::from_sidecar(path)? else {
+        return Ok(());
+    };
+    for named_img in images {
+        if !nine_slice::fits(&borders, named_img.img.width(), named_img.img.height()) {
+            return Err(format!(
+                "The borders for {} are wider or taller than the sprite",
+                named_img.name
+            )
+            .into());
+        }
+        named_img.borders = Some(borders);
+    }
+    Ok(())
+}
 
-    for path in paths {
-        let path = path?.path();
-        if let (Some(path_str), Some(fname)) = (path.to_str(), path.file_prefix()) {
-            match image::io::Reader::open(path_str) {
-                Ok(reader) => match reader.decode() {
-                    Ok(img) => {
+// a file's own pivot sidecar wins over pivots set in the art, which win over
+// the default for its directory
+fn apply_pivots(path: &Path, images: &mut [NamedDynamicImage]) -> Result<(), Box<dyn Error>> {
+    if images.is_empty() {
+        return Ok(());
+    }
+    let (sidecar, overrides) = match pivot::from_sidecar(path)? {
+        Some(sidecar) => (sidecar, true),
+        None => match pivot::folder_default(path)? {
+            Some(sidecar) => (sidecar, false),
+            None => return Ok(()),
+        },
+    };
+    for named_img in images {
+        if overrides || named_img.pivot.is_none() {
+            named_img.pivot = Some(sidecar.resolve(named_img.img.width(), named_img.img.height()));
+        }
+    }
+    Ok(())
+}
+
+// the sprites cut from one source file, named as they are in the data
+pub fn load_sprites(
+    options: ImportOptions,
+    path: &Path,
+) -> Result<Vec<(String, DynamicImage)>, Box<dyn Error>> {
+    let mut images = vec![];
+    load_file(options, path, &mut images, &mut vec![])?;
+    Ok(images
+        .into_iter()
+        .map(|named_img| (named_img.name, named_img.img))
+        .collect())
+}
+
+fn load_file(
+    options: ImportOptions,
+    path: &Path,
+    images: &mut Vec<NamedDynamicImage>,
+    animations: &mut Vec<Animation>,
+) -> Result<(), Box<dyn Error>> {
+    // sidecar files describing the images
+    if sidecar::is_sidecar(path) {
+        return Ok(());
+    }
+    if let (Some(path_str), Some(fname)) = (path.to_str(), path.file_prefix()) {
+        let fname = fname.to_string_lossy().to_string();
+        if aseprite::is_aseprite(path) {
+            match aseprite::import(path, &fname, options.split_layers) {
+                Ok((sprites, file_animations)) => {
+                    for sprite in sprites {
                         images.push(NamedDynamicImage {
-                            name: fname.to_string_lossy().to_string(),
-                            img,
+                            name: sprite.name,
+                            img: sprite.img,
+                            pivot: sprite.pivot,
+                            borders: sprite.borders,
                         });
                     }
-                    Err(_) => {
-                        println!("Error decoding {path_str} - skipping");
-                        continue;
+                    animations.extend(file_animations);
+                }
+                Err(err) => println!("Error decoding {path_str} - skipping: {err}"),
+            }
+            return Ok(());
+        }
+        if let Ok(format) = image::ImageFormat::from_path(path) {
+            match animation::decode_frames(BufReader::new(fs::File::open(path)?), format) {
+                Ok(Some(frames)) => {
+                    let mut anim = Animation {
+                        name: fname.clone(),
+                        frames: vec![],
+                        durations: vec![],
+                        direction: None,
+                    };
+                    for (i, (img, delay)) in frames.into_iter().enumerate() {
+                        let name = format!("{fname}_{i}");
+                        anim.frames.push(name.clone());
+                        anim.durations.push(delay);
+                        images.push(NamedDynamicImage::new(name, img));
                     }
-                },
-                Err(err) => {
-                    eprintln!("Error opening {path_str}");
-                    return Err(Box::new(err));
+                    animations.push(anim);
+                    return Ok(());
+                }
+                Ok(None) => (),
+                Err(_) => {
+                    println!("Error decoding {path_str} - skipping");
+                    return Ok(());
                 }
             }
         }
+        match image::io::Reader::open(path_str) {
+            Ok(reader) => match reader.decode() {
+                Ok(img) => {
+                    let (img, borders) = if nine_slice::is_nine_patch(path) {
+                        match nine_slice::strip_guides(&img) {
+                            Ok((img, borders)) => (img, Some(borders)),
+                            Err(err) => {
+                                println!("Error reading the nine patch guides of {path_str} - skipping: {err}");
+                                return Ok(());
+                            }
+                        }
+                    } else {
+                        (img, None)
+                    };
+                    let grid = match grid::from_sidecar(path) {
+                        Ok(Some(grid)) => Some((fname.clone(), grid)),
+                        Ok(None) if options.grid_names => {
+                            grid::from_name(&fname, img.width(), img.height())
+                        }
+                        Ok(None) => None,
+                        Err(err) => {
+                            println!("Error reading the grid for {path_str} - skipping: {err}");
+                            return Ok(());
+                        }
+                    };
+                    match grid {
+                        Some((name, grid)) => {
+                            for (name, cell) in grid::slice(&name, &img, &grid) {
+                                images.push(NamedDynamicImage::new(name, cell));
+                            }
+                        }
+                        None => images.push(NamedDynamicImage {
+                            borders,
+                            ..NamedDynamicImage::new(fname, img)
+                        }),
+                    }
+                }
+                Err(_) => {
+                    println!("Error decoding {path_str} - skipping");
+                    return Ok(());
+                }
+            },
+            Err(err) => {
+                eprintln!("Error opening {path_str}");
+                return Err(Box::new(err));
+            }
+        }
     }
+    Ok(())
+}
 
-    Ok(ImageCollection::new(images))
+fn new_sheet(depth: BitDepth, width: u32, height: u32) -> DynamicImage {
+    match depth {
+        BitDepth::Eight => DynamicImage::ImageRgba8(image::RgbaImage::new(width, height)),
+        BitDepth::Sixteen => DynamicImage::ImageRgba16(image::ImageBuffer::new(width, height)),
+        BitDepth::Float => DynamicImage::ImageRgba32F(image::ImageBuffer::new(width, height)),
+    }
 }
 
+type Placement<'a> = (&'a NamedDynamicImage, u32, u32, bool);
+
+// where each sprite goes in the sheet, without drawing it. `used` is the part
+// of the sheet holding sprites, all of it unless the sheet has a fixed size
+struct Arrangement<'a> {
+    width: u32,
+    height: u32,
+    used: (u32, u32),
+    padding: u32,
+    padded_area: u64,
+    layout: Layout,
+    // each sprite with its top left in the sheet and whether it was turned
+    placements: Vec<Placement<'a>>,
+    // sprites that didn't fit in a sheet of fixed size, with their padded area
+    unplaced: Vec<(&'a NamedDynamicImage, u64)>,
+}
+
+impl Arrangement<'_> {
+    // fewer sprites left out comes first, then the smaller and squarer packing
+    fn size(&self) -> (u64, u64, u64) {
+        let unplaced_area = self.unplaced.iter().map(|(_, area)| area).sum();
+        let (width, height) = (self.used.0 as u64, self.used.1 as u64);
+        (unplaced_area, width * height, width + height)
+    }
+
+    fn describe_unplaced(&self) -> String {
+        let sprites = self
+            .unplaced
+            .iter()
+            .map(|(named_img, _)| {
+                format!(
+                    "{} {}x{}",
+                    named_img.name,
+                    named_img.img.width(),
+                    named_img.img.height()
+                )
+            })
+            .collect::<Vec<String>>()
+            .join(", ");
+        let used_area: u64 = self
+            .placements
+            .iter()
+            .map(|(named_img, ..)| named_img.img.width() as u64 * named_img.img.height() as u64)
+            .sum();
+        let total_area = self.width as u64 * self.height as u64;
+        let occupancy = match total_area {
+            0 => 0.0,
+            total_area => used_area as f64 / total_area as f64 * 100.0,
+        };
+        let doesnt_fit = match self.unplaced.len() {
+            1 => "1 sprite doesn't fit".to_owned(),
+            n => format!("{n} sprites don't fit"),
+        };
+        format!(
+            "{doesnt_fit} in the {}x{} sheet, {occupancy:.1}% full, needing {} more pixels with padding: {sprites}",
+            self.width,
+            self.height,
+            self.size().0
+        )
+    }
+}
+
+// the smallest sheet from arranging with each heuristic in parallel, the
+// earliest on a tie
+fn arrange_best<'a>(
+    padding: u32,
+    alignment: u32,
+    sheet_size: Option<(u32, u32)>,
+    img_collection: &'a ImageCollection,
+    heuristics: &[Heuristic],
+) -> Result<(Heuristic, Arrangement<'a>), Box<dyn Error>> {
+    let arrangements: Vec<Result<Arrangement, String>> = heuristics
+        .par_iter()
+        .map(|heuristic| {
+            arrange(
+                padding,
+                alignment,
+                sheet_size,
+                &img_collection.sorted(heuristic.sort_key),
+                heuristic.leaf_choice,
+            )
+            .map_err(|err| err.to_string())
+        })
+        .collect();
+    let mut best: Option<(Heuristic, Arrangement)> = None;
+    for (heuristic, arrangement) in heuristics.iter().zip(arrangements) {
+        let arrangement = arrangement?;
+        if best
+            .as_ref()
+            .is_none_or(|(_, best)| arrangement.size() < best.size())
+        {
+            best = Some((*heuristic, arrangement));
+        }
+    }
+    let (heuristic, arrangement) = best.ok_or("No packing heuristics to try")?;
+    if heuristics.len() > 1 {
+        println!(
+            "Best of {} packings: {heuristic}, {}x{}",
+            heuristics.len(),
+            arrangement.used.0,
+            arrangement.used.1
+        );
+    }
+    Ok((heuristic, arrangement))
+}
+
+// searches for an insertion order giving a smaller sheet than the heuristic's
+fn optimize_arrangement<'a>(
+    padding: u32,
+    alignment: u32,
+    sheet_size: Option<(u32, u32)>,
+    img_collection: &'a ImageCollection,
+    heuristic: Heuristic,
+    arrangement: Arrangement<'a>,
+    settings: &optimize::Settings,
+) -> Result<Arrangement<'a>, Box<dyn Error>> {
+    let start = Instant::now();
+    let sorted = img_collection.sorted(heuristic.sort_key);
+    let used_area = sorted
+        .iter()
+        .map(|named_img| named_img.img.width() as u64 * named_img.img.height() as u64)
+        .sum();
+    let in_order = |order: &[usize], rotated: &[bool]| -> Vec<(&NamedDynamicImage, bool)> {
+        order
+            .iter()
+            .map(|i| (sorted[*i], rotated.get(*i) == Some(&true)))
+            .collect()
+    };
+    let rotated = match settings.allow_rotation {
+        true => vec![false; sorted.len()],
+        false => vec![],
+    };
+    let outcome = optimize::anneal(
+        (0..sorted.len()).collect(),
+        rotated,
+        |order, rotated| {
+            arrange_turned(
+                padding,
+                alignment,
+                sheet_size,
+                &in_order(order, rotated),
+                heuristic.leaf_choice,
+            )
+            .ok()
+            .filter(|arrangement| arrangement.unplaced.is_empty())
+            .map(|arrangement| arrangement.size().1)
+        },
+        used_area,
+        settings,
+    );
+
+    let (width, height) = arrangement.used;
+    let optimized = if outcome.cost < arrangement.size().1 {
+        arrange_turned(
+            padding,
+            alignment,
+            sheet_size,
+            &in_order(&outcome.order, &outcome.rotated),
+            heuristic.leaf_choice,
+        )?
+    } else {
+        arrangement
+    };
+    println!(
+        "Optimized over {} orders in {:.1}s: {width}x{height} to {}x{}",
+        outcome.iterations,
+        start.elapsed().as_secs_f64(),
+        optimized.used.0,
+        optimized.used.1
+    );
+    Ok(optimized)
+}
+
+// packs with a single heuristic
+#[cfg(test)]
 fn pack(
-    output_file_name: &str,
-    output_file_format: MetaDataFormat,
-    padding: u8,
-    img_collection: ImageCollection,
+    padding: u32,
+    alignment: u32,
+    depth: BitDepth,
+    img_collection: &ImageCollection,
+    heuristic: Heuristic,
 ) -> Result<PackedImage, Box<dyn Error>> {
+    Ok(render(
+        arrange(
+            padding,
+            alignment,
+            None,
+            &img_collection.sorted(heuristic.sort_key),
+            heuristic.leaf_choice,
+        )?,
+        depth,
+    ))
+}
+
+// packs the images in the order given
+fn arrange<'a>(
+    padding: u32,
+    alignment: u32,
+    sheet_size: Option<(u32, u32)>,
+    images: &[&'a NamedDynamicImage],
+    leaf_choice: LeafChoice,
+) -> Result<Arrangement<'a>, Box<dyn Error>> {
+    let unrotated: Vec<(&NamedDynamicImage, bool)> =
+        images.iter().map(|named_img| (*named_img, false)).collect();
+    arrange_turned(padding, alignment, sheet_size, &unrotated, leaf_choice)
+}
+
+// packs the images in the order given, turning those marked clockwise
+fn arrange_turned<'a>(
+    padding: u32,
+    alignment: u32,
+    sheet_size: Option<(u32, u32)>,
+    images: &[(&'a NamedDynamicImage, bool)],
+    leaf_choice: LeafChoice,
+) -> Result<Arrangement<'a>, Box<dyn Error>> {
+    // block compressed sheets keep every sprite on its own blocks
+    let align = |v: u32| v.div_ceil(alignment) * alignment;
+    let padding = align(padding);
+
     let mut data = vec![];
-    for named_img in img_collection.named_images.iter() {
+    for (named_img, rotated) in images {
+        let (width, height) = match rotated {
+            true => (named_img.img.height(), named_img.img.width()),
+            false => (named_img.img.width(), named_img.img.height()),
+        };
         data.push((
             DataSize {
-                width: named_img.img.width() + padding as u32,
-                height: named_img.img.height() + padding as u32,
+                width: align(width) + padding,
+                height: align(height) + padding,
             },
-            named_img,
+            (*named_img, *rotated),
         ));
     }
-    let mut tree = Tree2d::<&NamedDynamicImage>::new();
-    tree.insert_all(data)?;
+    let mut tree = match sheet_size {
+        Some((width, height)) => Tree2d::with_bounds(
+            leaf_choice,
+            width.saturating_sub(padding),
+            height.saturating_sub(padding),
+        ),
+        None => Tree2d::with_leaf_choice(leaf_choice),
+    };
+    let mut unplaced = vec![];
+    for (size, placed) in data {
+        match tree.insert(size.width, size.height, placed) {
+            Ok(_) => (),
+            // a sheet of fixed size leaves out whatever doesn't fit
+            Err(_) if sheet_size.is_some() => {
+                unplaced.push((placed.0, size.width as u64 * size.height as u64))
+            }
+            Err(err) => return Err(err),
+        }
+    }
     let flattened = tree.flatten();
     let bb = tree.get_total_bounding_box();
-    let mut img_packed =
-        image::RgbaImage::new(bb.width + padding as u32, bb.height + padding as u32);
-    let mut sprite_data = vec![];
+    let padded_area = flattened
+        .iter()
+        .map(|(_, bb)| bb.width as u64 * bb.height as u64)
+        .sum();
+    let placements = flattened
+        .into_iter()
+        .map(|((named_img, rotated), bb)| (*named_img, bb.x + padding, bb.y + padding, *rotated))
+        .collect();
 
-    for (named_img, bb) in flattened {
-        let x = bb.x as i64 + padding as i64;
-        let y = bb.y as i64 + padding as i64;
-        image::imageops::replace(&mut img_packed, &named_img.img, x, y);
+    let used = (bb.width + padding, bb.height + padding);
+    let (width, height) = sheet_size.unwrap_or(used);
 
-        let sd = SpriteData {
-            name: named_img.name.to_owned(),
-            x: x as u32,
-            y: y as u32,
-            width: named_img.img.width(),
-            height: named_img.img.height(),
-        };
-        sprite_data.push(sd);
+    Ok(Arrangement {
+        width,
+        height,
+        used,
+        padding,
+        padded_area,
+        layout: Layout {
+            free_leaves: tree.free_leaves(),
+            splits: tree.splits(),
+        },
+        placements,
+        unplaced,
+    })
+}
+
+// keeps every sprite the same size as in the previous sheet where it was, and
+// fits the rest, in the order given, into the space left in a sheet of the
+// same size. The error says why the previous layout can't be kept
+fn arrange_stable<'a>(
+    padding: u32,
+    alignment: u32,
+    sheet_size: Option<(u32, u32)>,
+    images: &[&'a NamedDynamicImage],
+    previous: &Atlas,
+) -> Result<Arrangement<'a>, String> {
+    let align = |v: u32| v.div_ceil(alignment) * alignment;
+    let padding = align(padding);
+    if let Some(previous_padding) = previous.padding.filter(|p| *p != padding) {
+        return Err(format!(
+            "it was packed with a padding of {previous_padding} rather than {padding}"
+        ));
     }
+    let (width, height) = previous.size.ok_or("it doesn't record the sheet size")?;
+    if let Some((fixed_width, fixed_height)) = sheet_size.filter(|size| *size != (width, height)) {
+        return Err(format!(
+            "its sheet is {width}x{height} rather than the --size of {fixed_width}x{fixed_height}"
+        ));
+    }
+    // rects in the coordinates of the tree, each sprite with its padding up and left
+    let bounds = (
+        width.saturating_sub(padding),
+        height.saturating_sub(padding),
+    );
+    let regions: HashMap<String, &Region> = previous
+        .regions
+        .iter()
+        .map(|region| (atlas::normalise_name(&region.name), region))
+        .collect();
+    // sprites corral turned stay turned
+    let unchanged = |named_img: &NamedDynamicImage, region: &Region| {
+        region.rotation != Rotation::CounterClockwise
+            && (region.width, region.height) == (named_img.img.width(), named_img.img.height())
+            && region.x >= padding
+            && region.y >= padding
+            && (region.x - padding).is_multiple_of(alignment)
+            && (region.y - padding).is_multiple_of(alignment)
+    };
 
-    sprite_data.sort_by(|a, b| a.name.partial_cmp(&b.name).unwrap());
+    let padded = |(width, height): (u32, u32), x, y| BoundingBox {
+        x,
+        y,
+        width: align(width) + padding,
+        height: align(height) + padding,
+    };
 
-    let meta_data = match output_file_format {
-        MetaDataFormat::Json => {
-            let json_string: String = sprite_data
-                .iter()
-                .map(|sd| sd.to_json_string())
-                .collect::<Vec<String>>()
-                .join(",");
-            "[".to_owned() + &json_string + "]\n"
-        }
-        MetaDataFormat::Lua => {
-            let module_name = output_file_name.split('.').collect::<Vec<&str>>()[0].to_owned();
-            let lua_string: String = sprite_data
-                .iter()
-                .map(|sd| sd.to_lua_string())
-                .collect::<Vec<String>>()
-                .join(",\n");
-            format!("local {fname} = {{\n", fname = module_name)
-                + &lua_string
-                + &format!("\n}}\n\nreturn {fname}\n", fname = module_name)
+    let mut space = stable::FreeSpace::new(bounds);
+    let mut padded_area = 0;
+    let mut placements = vec![];
+    let mut moved = vec![];
+    for named_img in images {
+        match regions.get(&atlas::normalise_name(&named_img.name)) {
+            Some(region) if unchanged(named_img, region) => {
+                let rect = padded(region.sheet_size(), region.x - padding, region.y - padding);
+                if !space.is_free(&rect) {
+                    return Err(format!("{} overlaps another sprite", region.name));
+                }
+                space.take(&rect);
+                padded_area += rect.width as u64 * rect.height as u64;
+                let rotated = region.rotation == Rotation::Clockwise;
+                placements.push((*named_img, region.x, region.y, rotated));
+            }
+            _ => moved.push(*named_img),
         }
-    };
+    }
+    let kept = placements.len();
+    for named_img in &moved {
+        let size = padded((named_img.img.width(), named_img.img.height()), 0, 0);
+        let (x, y) = space
+            .find_spot(size.width, size.height)
+            .ok_or_else(|| format!("{} doesn't fit in the space left", named_img.name))?;
+        space.take(&BoundingBox { x, y, ..size });
+        padded_area += size.width as u64 * size.height as u64;
+        placements.push((*named_img, x + padding, y + padding, false));
+    }
+    println!(
+        "Kept {kept} sprites in place and placed {} new or resized sprites",
+        moved.len()
+    );
 
-    Ok(PackedImage {
-        img: DynamicImage::ImageRgba8(img_packed),
-        meta_data,
+    Ok(Arrangement {
+        width,
+        height,
+        used: (width, height),
+        padding,
+        padded_area,
+        layout: Layout::default(),
+        placements,
+        unplaced: vec![],
     })
 }
 
+fn render(arrangement: Arrangement, depth: BitDepth) -> PackedImage {
+    let mut img_packed = new_sheet(depth, arrangement.width, arrangement.height);
+    let turned: Vec<Option<DynamicImage>> = arrangement
+        .placements
+        .par_iter()
+        .map(|(named_img, _, _, rotated)| rotated.then(|| named_img.img.rotate90()))
+        .collect();
+    let mut sprite_data = vec![];
+    let mut sprites = vec![];
+    for ((named_img, x, y, rotated), turned) in arrangement.placements.into_iter().zip(&turned) {
+        sprites.push((turned.as_ref().unwrap_or(&named_img.img), x, y));
+        sprite_data.push(SpriteData {
+            name: named_img.name.to_owned(),
+            x,
+            y,
+            width: named_img.img.width(),
+            height: named_img.img.height(),
+            rotated,
+            pivot: named_img.pivot,
+            borders: named_img.borders,
+        });
+    }
+    blit_all(&mut img_packed, &sprites);
+    sprite_data.sort_by(|a, b| a.name.partial_cmp(&b.name).unwrap());
+
+    PackedImage {
+        img: img_packed,
+        padding: arrangement.padding,
+        padded_area: arrangement.padded_area,
+        layout: arrangement.layout,
+        sprite_data,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -253,9 +1349,7 @@ mod tests {
         let mut expected_output_img = image::RgbaImage::new(w + padding * 2, h + padding * 2);
         for i in 0..w + padding * 2 {
             for j in 0..h + padding * 2 {
-                let color = if i < padding || j < padding {
-                    image::Rgba([0, 0, 0, 0])
-                } else if i >= padding + w || j >= padding + h {
+                let color = if i < padding || j < padding || i >= padding + w || j >= padding + h {
                     image::Rgba([0, 0, 0, 0])
                 } else {
                     image::Rgba([255, 0, 0, 255])
@@ -264,16 +1358,17 @@ mod tests {
             }
         }
 
-        let img_collection = ImageCollection::new(vec![NamedDynamicImage {
-            name: "red_pixel".to_owned(),
-            img: make_rect(w, h),
-        }]);
+        let img_collection = ImageCollection::new(vec![NamedDynamicImage::new(
+            "red_pixel".to_owned(),
+            make_rect(w, h),
+        )]);
 
         if let Some(img) = pack(
-            &"out.png",
-            MetaDataFormat::Json,
-            padding as u8,
-            img_collection,
+            padding,
+            1,
+            BitDepth::Eight,
+            &img_collection,
+            Heuristic::default(),
         )?
         .img
         .as_rgba8()
@@ -285,6 +1380,389 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn pack_aligned_to_blocks() -> Result<(), Box<dyn Error>> {
+        let img_collection = ImageCollection::new(vec![
+            NamedDynamicImage::new("a".to_owned(), make_rect(5, 3)),
+            NamedDynamicImage::new("b".to_owned(), make_rect(2, 7)),
+        ]);
+
+        let packed = pack(2, 4, BitDepth::Eight, &img_collection, Heuristic::default())?;
+        assert_eq!(packed.img.width() % 4, 0);
+        assert_eq!(packed.img.height() % 4, 0);
+        for sd in packed.sprite_data {
+            assert_eq!(sd.x % 4, 0);
+            assert_eq!(sd.y % 4, 0);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn scaled_data_files_keep_apart() -> Result<(), Box<dyn Error>> {
+        let config = Config::parse(&[
+            "corral".to_owned(),
+            "in".to_owned(),
+            "out/atlas.png".to_owned(),
+            "--data-fmt=lua".to_owned(),
+        ])?;
+        assert_eq!(
+            data_file_name(&config, "out/atlas.png", ""),
+            "out/atlas.lua"
+        );
+        assert_eq!(
+            data_file_name(&config, "out/atlas.png", "@0.5x"),
+            "out/atlas@0.5x.lua"
+        );
+        assert_ne!(
+            data_file_name(&config, "out/atlas.png", "@0.5x"),
+            data_file_name(&config, "out/atlas.png", "@0.25x")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn sprite_names_clash_across_files() -> Result<(), Box<dyn Error>> {
+        let config = Config::parse(&["corral".to_owned(), "in".to_owned(), "out.png".to_owned()])?;
+        let red = PathBuf::from("test/squares/rectangle_red.png");
+        let blue = PathBuf::from("test/squares/rectangle_blue.png");
+        let (img_collection, _) = load_all(&config, &[red.clone(), blue])?;
+        assert_eq!(img_collection.named_images.len(), 2);
+
+        // the same name reached from another directory
+        let other = PathBuf::from("test/squares-different-sizes/../squares/rectangle_red.png");
+        let err = load_all(&config, &[red, other])
+            .err()
+            .map(|err| err.to_string());
+        assert_eq!(
+            err.as_deref(),
+            Some("Sprites from different files share a name, rename or regroup the files: rectangle_red from test/squares/rectangle_red.png, test/squares-different-sizes/../squares/rectangle_red.png")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn scale_grid_lands_on_whole_pixels() {
+        assert_eq!(scale_grid(&[1.0, 0.5, 0.25]), 4);
+        assert_eq!(scale_grid(&[1.0, 0.75]), 4);
+        assert_eq!(scale_grid(&[0.4]), 5);
+        assert_eq!(scale_grid(&[2.0, 0.5, 0.3]), 10);
+        for scales in [[1.0, 0.75], [0.4, 0.4], [0.5, 0.3]] {
+            let grid = scale_grid(&scales) as f32;
+            for scale in scales {
+                assert_eq!((grid * scale).fract(), 0.0);
+            }
+        }
+    }
+
+    #[test]
+    fn fixed_size_lists_what_doesnt_fit() -> Result<(), Box<dyn Error>> {
+        let img_collection = ImageCollection::new(vec![
+            NamedDynamicImage::new("a".to_owned(), make_rect(4, 4)),
+            NamedDynamicImage::new("b".to_owned(), make_rect(4, 4)),
+            NamedDynamicImage::new("c".to_owned(), make_rect(4, 4)),
+        ]);
+        let sorted = img_collection.sorted(SortKey::Area);
+        let arrangement = arrange(2, 1, Some((14, 8)), &sorted, LeafChoice::MostSquare)?;
+        assert_eq!((arrangement.width, arrangement.height), (14, 8));
+        assert_eq!(arrangement.placements.len(), 2);
+        assert_eq!(
+            arrangement.describe_unplaced(),
+            "1 sprite doesn't fit in the 14x8 sheet, 28.6% full, needing 36 more pixels with padding: c 4x4"
+        );
+
+        let arrangement = arrange(2, 1, Some((14, 14)), &sorted, LeafChoice::MostSquare)?;
+        assert!(arrangement.unplaced.is_empty());
+        assert_eq!(arrangement.used, (14, 14));
+        Ok(())
+    }
+
+    #[test]
+    fn stable_keeps_previous_positions() -> Result<(), Box<dyn Error>> {
+        let img_collection = ImageCollection::new(vec![
+            NamedDynamicImage::new("a".to_owned(), make_rect(4, 4)),
+            NamedDynamicImage::new("b".to_owned(), make_rect(4, 2)),
+            NamedDynamicImage::new("c".to_owned(), make_rect(2, 2)),
+        ]);
+        let region = |name: &str, x, y| Region {
+            name: name.to_owned(),
+            x,
+            y,
+            width: 4,
+            height: 4,
+            rotation: Rotation::None,
+            offset: (0, 0),
+            source_size: (4, 4),
+        };
+        let mut previous = Atlas {
+            regions: vec![region("A", 2, 2), region("b", 8, 2)],
+            size: Some((14, 12)),
+            padding: Some(2),
+            ..Default::default()
+        };
+
+        // b shrank, so it is placed again along with the new c
+        let arrangement =
+            arrange_stable(2, 1, None, &img_collection.sorted(SortKey::Area), &previous)?;
+        let placements: Vec<(&str, u32, u32)> = arrangement
+            .placements
+            .iter()
+            .map(|(named_img, x, y, _)| (named_img.name.as_str(), *x, *y))
+            .collect();
+        assert_eq!(placements, vec![("a", 2, 2), ("b", 8, 2), ("c", 8, 6)]);
+        assert_eq!((arrangement.width, arrangement.height), (14, 12));
+
+        previous.size = Some((14, 8));
+        assert_eq!(
+            arrange_stable(2, 1, None, &img_collection.sorted(SortKey::Area), &previous).err(),
+            Some("c doesn't fit in the space left".to_owned())
+        );
+        previous.padding = Some(1);
+        assert_eq!(
+            arrange_stable(2, 1, None, &img_collection.sorted(SortKey::Area), &previous).err(),
+            Some("it was packed with a padding of 1 rather than 2".to_owned())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn pack_sixteen_bit() -> Result<(), Box<dyn Error>> {
+        let mut img = image::ImageBuffer::<image::Rgba<u16>, Vec<u16>>::new(2, 2);
+        for pixel in img.pixels_mut() {
+            *pixel = image::Rgba([1234, 40000, 65535, 65535]);
+        }
+        let img_collection = ImageCollection::new(vec![NamedDynamicImage::new(
+            "deep".to_owned(),
+            DynamicImage::ImageRgba16(img),
+        )]);
+
+        let packed = pack(
+            1,
+            1,
+            BitDepth::Sixteen,
+            &img_collection,
+            Heuristic::default(),
+        )?;
+        let packed = packed.img.as_rgba16().unwrap();
+        assert_eq!(
+            packed.get_pixel(1, 1),
+            &image::Rgba([1234, 40000, 65535, 65535])
+        );
+        assert_eq!(packed.get_pixel(0, 0), &image::Rgba([0, 0, 0, 0]));
+        Ok(())
+    }
+
+    #[test]
+    fn rescale_keeps_proportional_layout() -> Result<(), Box<dyn Error>> {
+        let img_collection = ImageCollection::new(vec![
+            NamedDynamicImage::new("a".to_owned(), make_rect(16, 8)),
+            NamedDynamicImage::new("b".to_owned(), make_rect(8, 8)),
+        ]);
+
+        let packed = pack(
+            2 * 4,
+            4,
+            BitDepth::Eight,
+            &img_collection,
+            Heuristic::default(),
+        )?;
+        let quarter = packed.rescale(&img_collection, BitDepth::Eight, 0.25, FilterType::Nearest);
+        assert_eq!(quarter.img.width() * 4, packed.img.width());
+        assert_eq!(quarter.img.height() * 4, packed.img.height());
+        for (full, small) in packed.sprite_data.iter().zip(quarter.sprite_data.iter()) {
+            assert_eq!(full.name, small.name);
+            assert_eq!(
+                (small.x * 4, small.y * 4, small.width * 4, small.height * 4),
+                (full.x, full.y, full.width, full.height)
+            );
+            let pixel = quarter.img.as_rgba8().unwrap().get_pixel(small.x, small.y);
+            assert_eq!(pixel, &image::Rgba([255, 0, 0, 255]));
+        }
+        Ok(())
+    }
+
+    fn load_fixtures() -> Result<Vec<NamedDynamicImage>, Box<dyn Error>> {
+        let mut images = vec![];
+        for dir in ["test/squares", "test/squares-different-sizes"] {
+            let mut paths = fs::read_dir(dir)?
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<Result<Vec<_>, _>>()?;
+            paths.sort();
+            for path in paths {
+                let name = path.file_prefix().unwrap().to_string_lossy().to_string();
+                images.push(NamedDynamicImage::new(name, image::open(&path)?));
+            }
+        }
+        Ok(images)
+    }
+
+    fn pack_to_bytes(images: Vec<NamedDynamicImage>) -> Result<(Vec<u8>, String), Box<dyn Error>> {
+        let packed = pack(
+            2,
+            1,
+            BitDepth::Eight,
+            &ImageCollection::new(images),
+            Heuristic::default(),
+        )?;
+        let mut png = vec![];
+        write_png(&packed.img, &mut png)?;
+        let sheet_data = SheetData {
+            width: packed.img.width(),
+            height: packed.img.height(),
+            padding: packed.padding,
+            ..Default::default()
+        };
+        let meta_data = meta_data::format(
+            "out",
+            MetaDataFormat::Json,
+            &packed.sprite_data,
+            &sheet_data,
+        );
+        Ok((png, meta_data))
+    }
+
+    #[test]
+    fn pack_is_independent_of_input_order() -> Result<(), Box<dyn Error>> {
+        let expected = pack_to_bytes(load_fixtures()?)?;
+        let count = load_fixtures()?.len();
+        for shuffle in 1..count * 2 {
+            let mut images = load_fixtures()?;
+            images.rotate_left(shuffle % count);
+            if shuffle >= count {
+                images.reverse();
+            }
+            // swap a pair of the equally sized squares too
+            images.swap(shuffle % count, (shuffle * 3) % count);
+            assert!(
+                pack_to_bytes(images)? == expected,
+                "shuffle {shuffle} changed the output"
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn sort_keys_break_ties() {
+        let img_collection = ImageCollection::new(vec![
+            NamedDynamicImage::new("wide".to_owned(), make_rect(8, 2)),
+            NamedDynamicImage::new("tall".to_owned(), make_rect(2, 8)),
+            NamedDynamicImage::new("b".to_owned(), make_rect(4, 4)),
+            NamedDynamicImage::new("a".to_owned(), make_rect(4, 4)),
+        ]);
+        let names = |sort_key| -> Vec<String> {
+            img_collection
+                .sorted(sort_key)
+                .iter()
+                .map(|named_img| named_img.name.to_owned())
+                .collect()
+        };
+        assert_eq!(names(SortKey::Area), vec!["tall", "a", "b", "wide"]);
+        assert_eq!(names(SortKey::Width), vec!["wide", "a", "b", "tall"]);
+        assert_eq!(names(SortKey::None), vec!["a", "b", "tall", "wide"]);
+    }
+
+    #[test]
+    fn best_is_no_larger_than_any_heuristic() -> Result<(), Box<dyn Error>> {
+        let img_collection = ImageCollection::new(load_fixtures()?);
+        let area = |packed: &PackedImage| packed.img.width() * packed.img.height();
+        let (_, best) = arrange_best(2, 1, None, &img_collection, &Heuristic::all())?;
+        let best = render(best, BitDepth::Eight);
+        for heuristic in Heuristic::all() {
+            let packed = pack(2, 1, BitDepth::Eight, &img_collection, heuristic)?;
+            assert!(area(&best) <= area(&packed), "{heuristic} beat --best");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn optimize_never_grows_the_sheet() -> Result<(), Box<dyn Error>> {
+        let img_collection = ImageCollection::new(load_fixtures()?);
+        let heuristic = Heuristic::default();
+        let sorted = img_collection.sorted(heuristic.sort_key);
+        let optimize = |allow_rotation| {
+            let arrangement = arrange(2, 1, None, &sorted, heuristic.leaf_choice)?;
+            let settings = optimize::Settings {
+                seed: 1,
+                time_budget: None,
+                iterations: Some(200),
+                target_occupancy: None,
+                allow_rotation,
+            };
+            optimize_arrangement(
+                2,
+                1,
+                None,
+                &img_collection,
+                heuristic,
+                arrangement,
+                &settings,
+            )
+        };
+        let initial = arrange(2, 1, None, &sorted, heuristic.leaf_choice)?;
+        for allow_rotation in [false, true] {
+            let optimized = optimize(allow_rotation)?;
+            assert!(optimized.size() <= initial.size());
+            assert_eq!(optimized.placements.len(), initial.placements.len());
+            let again = optimize(allow_rotation)?;
+            assert_eq!(
+                (again.width, again.height),
+                (optimized.width, optimized.height)
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn rotated_sprites_are_turned_clockwise() -> Result<(), Box<dyn Error>> {
+        let mut img = image::RgbaImage::from_pixel(3, 2, image::Rgba([255, 0, 0, 255]));
+        img.put_pixel(0, 0, image::Rgba([0, 255, 0, 255]));
+        let named_img = NamedDynamicImage::new("a".to_owned(), DynamicImage::ImageRgba8(img));
+        let arrangement =
+            arrange_turned(0, 1, None, &[(&named_img, true)], LeafChoice::MostSquare)?;
+        let packed = render(arrangement, BitDepth::Eight);
+        let sheet = packed.img.to_rgba8();
+        assert_eq!(sheet.dimensions(), (2, 3));
+        assert_eq!(*sheet.get_pixel(1, 0), image::Rgba([0, 255, 0, 255]));
+
+        let sd = &packed.sprite_data[0];
+        assert_eq!((sd.width, sd.height, sd.rotated), (3, 2, true));
+        let region = Region {
+            name: sd.name.to_owned(),
+            x: sd.x,
+            y: sd.y,
+            width: sd.width,
+            height: sd.height,
+            rotation: Rotation::Clockwise,
+            offset: (0, 0),
+            source_size: (sd.width, sd.height),
+        };
+        assert_eq!(
+            crate::unpack::extract(&sheet, &region)?,
+            named_img.img.to_rgba8()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn blit_across_bands() {
+        let mut sheet = DynamicImage::ImageRgba8(image::RgbaImage::new(40, 40));
+        let mut expected = image::RgbaImage::new(40, 40);
+        let tall = make_rect(3, 30);
+        let wide = make_rect(30, 2);
+        blit_all(&mut sheet, &[(&tall, 1, 5), (&wide, 9, 15)]);
+        image::imageops::replace(&mut expected, &tall, 1, 5);
+        image::imageops::replace(&mut expected, &wide, 9, 15);
+        assert!(sheet.as_rgba8().unwrap() == &expected);
+    }
+
+    #[test]
+    fn suffixed_file_names() {
+        assert_eq!(suffixed_file_name("out/sheet.png", ""), "out/sheet.png");
+        assert_eq!(
+            suffixed_file_name("out/sheet.png", "@2x"),
+            "out/sheet@2x.png"
+        );
+    }
+
     // #[test]
     // fn pack_many() -> Result<(), Box<dyn Error>> {
     //     let dims = vec![
@@ -0,0 +1,431 @@
+use std::collections::{BTreeSet, HashMap};
+use std::error::Error;
+use std::fs;
+use std::io::BufWriter;
+use std::path::Path;
+
+use image::RgbaImage;
+
+use crate::bounding_box::BoundingBox;
+
+const TRANSPARENT: [u8; 4] = [0, 0, 0, 0];
+
+pub struct Palette {
+    colours: Vec<[u8; 4]>,
+    lookup: HashMap<[u8; 4], u8>,
+}
+
+impl Palette {
+    fn new(colours: Vec<[u8; 4]>) -> Result<Palette, Box<dyn Error>> {
+        if colours.is_empty() || colours.len() > 256 {
+            return Err(format!(
+                "A palette must have between 1 and 256 colours, found {}",
+                colours.len()
+            )
+            .into());
+        }
+        let mut lookup = HashMap::new();
+        for (i, colour) in colours.iter().enumerate() {
+            lookup.entry(*colour).or_insert(i as u8);
+        }
+        Ok(Palette { colours, lookup })
+    }
+
+    pub fn load(path: &str) -> Result<Palette, Box<dyn Error>> {
+        let extension = Path::new(path)
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase());
+        let colours = match extension.as_deref() {
+            Some("gpl") => parse_gpl(&fs::read_to_string(path)?)?,
+            Some("hex") => parse_hex(&fs::read_to_string(path)?)?,
+            _ => {
+                let swatch = image::open(path)?.to_rgba8();
+                let mut colours = vec![];
+                for pixel in swatch.pixels() {
+                    if !colours.contains(&pixel.0) {
+                        colours.push(pixel.0);
+                    }
+                }
+                colours
+            }
+        };
+        Palette::new(colours)
+    }
+
+    // every fully transparent pixel shares one entry, so the empty sheet area
+    // reuses a transparent colour of the sprites when they have one. 256 colours
+    // none of which is transparent are one too many, the rarest is then merged
+    // into its nearest neighbour with a warning rather than quantizing them all
+    fn exact(img: &RgbaImage) -> Option<Palette> {
+        let mut counts: HashMap<[u8; 4], usize> = HashMap::new();
+        for pixel in img.pixels() {
+            *counts.entry(normalise(pixel.0)).or_default() += 1;
+        }
+        let mut colours: BTreeSet<[u8; 4]> = counts.keys().copied().collect();
+        if colours.len() == 257 {
+            let rarest = colours
+                .iter()
+                .filter(|colour| **colour != TRANSPARENT)
+                .min_by_key(|colour| (counts[*colour], **colour))
+                .copied()?;
+            println!(
+                "Warning: the sprites use 256 colours and none is fully transparent, leaving no palette entry for the empty sheet area. {count} pixels of #{hex} are remapped to the nearest colour, reduce the sprites to 255 colours to keep them exact",
+                count = counts[&rarest],
+                hex = rarest.iter().map(|c| format!("{c:02x}")).collect::<String>()
+            );
+            colours.remove(&rarest);
+        }
+        if colours.len() > 256 {
+            None
+        } else {
+            Palette::new(colours.into_iter().collect()).ok()
+        }
+    }
+
+    fn quantize(img: &RgbaImage) -> Result<Palette, Box<dyn Error>> {
+        let opaque: Vec<u8> = img
+            .pixels()
+            .filter(|pixel| pixel[3] > 0)
+            .flat_map(|pixel| pixel.0)
+            .collect();
+        let mut colours = vec![TRANSPARENT];
+        if !opaque.is_empty() {
+            let quantizer = color_quant::NeuQuant::new(10, 255, &opaque);
+            for colour in quantizer.color_map_rgba().chunks(4) {
+                let colour = normalise([colour[0], colour[1], colour[2], colour[3]]);
+                if !colours.contains(&colour) {
+                    colours.push(colour);
+                }
+            }
+        }
+        Palette::new(colours)
+    }
+
+    fn with_transparency(self) -> Result<Palette, Box<dyn Error>> {
+        if self.colours.iter().any(|colour| colour[3] == 0) {
+            return Ok(self);
+        }
+        let mut colours = vec![TRANSPARENT];
+        colours.extend(self.colours);
+        Palette::new(colours)
+            .map_err(|_| "The palette needs a free entry for transparent pixels".into())
+    }
+
+    fn transparent_index(&self) -> u8 {
+        match self.lookup.get(&TRANSPARENT) {
+            Some(index) => *index,
+            None => self
+                .colours
+                .iter()
+                .position(|colour| colour[3] == 0)
+                .unwrap_or(0) as u8,
+        }
+    }
+
+    fn nearest(&mut self, colour: [u8; 4]) -> u8 {
+        if let Some(index) = self.lookup.get(&colour) {
+            return *index;
+        }
+        let mut best = 0;
+        let mut best_distance = i32::MAX;
+        for (i, candidate) in self.colours.iter().enumerate() {
+            let distance: i32 = (0..4)
+                .map(|c| (candidate[c] as i32 - colour[c] as i32).pow(2))
+                .sum();
+            if distance < best_distance {
+                best = i as u8;
+                best_distance = distance;
+            }
+        }
+        self.lookup.insert(colour, best);
+        best
+    }
+}
+
+fn normalise(colour: [u8; 4]) -> [u8; 4] {
+    if colour[3] == 0 {
+        TRANSPARENT
+    } else {
+        colour
+    }
+}
+
+fn parse_gpl(text: &str) -> Result<Vec<[u8; 4]>, Box<dyn Error>> {
+    let mut colours = vec![];
+    for line in text.lines() {
+        let line = line.trim();
+        // colour names can hold a colon too, only these keys are headers
+        let header = line
+            .split_once(':')
+            .is_some_and(|(key, _)| matches!(key.trim(), "Name" | "Columns"));
+        if line.is_empty() || line.starts_with('#') || line.starts_with("GIMP Palette") || header {
+            continue;
+        }
+        let values: Vec<&str> = line.split_whitespace().take(3).collect();
+        if values.len() != 3 {
+            return Err(format!("Invalid gpl palette entry `{line}`").into());
+        }
+        colours.push([
+            values[0].parse()?,
+            values[1].parse()?,
+            values[2].parse()?,
+            255,
+        ]);
+    }
+    Ok(colours)
+}
+
+fn parse_hex(text: &str) -> Result<Vec<[u8; 4]>, Box<dyn Error>> {
+    let mut colours = vec![];
+    for line in text.lines() {
+        let line = line.trim().trim_start_matches('#');
+        if line.is_empty() {
+            continue;
+        }
+        let value = u32::from_str_radix(line, 16)?;
+        match line.len() {
+            6 => colours.push([(value >> 16) as u8, (value >> 8) as u8, value as u8, 255]),
+            8 => colours.push([
+                (value >> 24) as u8,
+                (value >> 16) as u8,
+                (value >> 8) as u8,
+                value as u8,
+            ]),
+            _ => return Err(format!("Invalid hex palette entry `{line}`").into()),
+        }
+    }
+    Ok(colours)
+}
+
+fn index_pixels(img: &RgbaImage, palette: &mut Palette, dither: bool) -> Vec<u8> {
+    let (width, height) = (img.width() as usize, img.height() as usize);
+    let transparent_index = palette.transparent_index();
+    let mut indices = vec![0u8; width * height];
+    let mut error = vec![[0f32; 4]; if dither { width * height } else { 0 }];
+
+    for y in 0..height {
+        for x in 0..width {
+            let i = y * width + x;
+            let pixel = img.get_pixel(x as u32, y as u32).0;
+            if pixel[3] == 0 {
+                indices[i] = transparent_index;
+                continue;
+            }
+            if !dither {
+                indices[i] = palette.nearest(pixel);
+                continue;
+            }
+
+            let mut wanted = [0u8; 4];
+            for c in 0..4 {
+                wanted[c] = (pixel[c] as f32 + error[i][c]).round().clamp(0.0, 255.0) as u8;
+            }
+            wanted[3] = wanted[3].max(1);
+            let index = palette.nearest(wanted);
+            indices[i] = index;
+
+            // floyd steinberg, only spread into pixels that are part of a sprite
+            let chosen = palette.colours[index as usize];
+            let neighbours = [(1i64, 0i64, 7.0), (-1, 1, 3.0), (0, 1, 5.0), (1, 1, 1.0)];
+            for (dx, dy, weight) in neighbours {
+                let (nx, ny) = (x as i64 + dx, y as i64 + dy);
+                if nx < 0 || nx >= width as i64 || ny >= height as i64 {
+                    continue;
+                }
+                if img.get_pixel(nx as u32, ny as u32)[3] == 0 {
+                    continue;
+                }
+                let n = ny as usize * width + nx as usize;
+                for c in 0..4 {
+                    error[n][c] += (wanted[c] as f32 - chosen[c] as f32) * weight / 16.0;
+                }
+            }
+        }
+    }
+    indices
+}
+
+pub fn write_indexed(
+    output_file: &str,
+    img: &RgbaImage,
+    sprites: &[(&str, BoundingBox)],
+    palette_file: Option<&str>,
+    dither: bool,
+) -> Result<(), Box<dyn Error>> {
+    let (mut palette, dither) = match palette_file {
+        Some(palette_file) => (Palette::load(palette_file)?.with_transparency()?, dither),
+        None => match Palette::exact(img) {
+            Some(palette) => (palette, false),
+            None => (Palette::quantize(img)?, dither),
+        },
+    };
+
+    let indices = index_pixels(img, &mut palette, dither);
+    report_remapped(img, &indices, &palette, sprites);
+
+    let buf = BufWriter::new(fs::File::create(output_file)?);
+    let mut encoder = png::Encoder::new(buf, img.width(), img.height());
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(png::BitDepth::Eight);
+    // fixed rather than default settings so identical sheets give identical files
+    encoder.set_compression(png::Compression::Best);
+    encoder.set_filter(png::FilterType::NoFilter);
+    encoder.set_adaptive_filter(png::AdaptiveFilterType::NonAdaptive);
+    encoder.set_palette(
+        palette
+            .colours
+            .iter()
+            .flat_map(|colour| [colour[0], colour[1], colour[2]])
+            .collect::<Vec<u8>>(),
+    );
+    let mut alphas: Vec<u8> = palette.colours.iter().map(|colour| colour[3]).collect();
+    while alphas.last() == Some(&255) {
+        alphas.pop();
+    }
+    if !alphas.is_empty() {
+        encoder.set_trns(alphas);
+    }
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&indices)?;
+    Ok(())
+}
+
+fn report_remapped(
+    img: &RgbaImage,
+    indices: &[u8],
+    palette: &Palette,
+    sprites: &[(&str, BoundingBox)],
+) {
+    for (name, bb) in sprites {
+        let mut remapped = 0;
+        for y in bb.y..bb.y + bb.height {
+            for x in bb.x..bb.x + bb.width {
+                let index = indices[(y * img.width() + x) as usize];
+                let original = normalise(img.get_pixel(x, y).0);
+                if palette.colours[index as usize] != original {
+                    remapped += 1;
+                }
+            }
+        }
+        if remapped > 0 {
+            println!(
+                "{name}: {remapped} of {total} pixels remapped to the palette",
+                total = bb.area()
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradient(w: u32, h: u32) -> RgbaImage {
+        let mut img = RgbaImage::new(w, h);
+        for (x, y, pixel) in img.enumerate_pixels_mut() {
+            *pixel = image::Rgba([x as u8, y as u8, 128, 255]);
+        }
+        img
+    }
+
+    #[test]
+    fn parse_gpl_palette() -> Result<(), Box<dyn Error>> {
+        let text = "GIMP Palette\nName: Test\nColumns: 2\n# comment\n255   0   0\tRed\n  0 255   0 Green\n  0   0 255 Sky: noon\n";
+        assert_eq!(
+            parse_gpl(text)?,
+            vec![[255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 255, 255]]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parse_hex_palette() -> Result<(), Box<dyn Error>> {
+        let text = "ff0000\n#00ff00\n0000ff80\n";
+        assert_eq!(
+            parse_hex(text)?,
+            vec![[255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 255, 128]]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn exact_palette_is_lossless() {
+        let mut img = gradient(16, 8);
+        img.put_pixel(0, 0, image::Rgba([12, 34, 56, 0]));
+        let mut palette = Palette::exact(&img).unwrap();
+        let indices = index_pixels(&img, &mut palette, false);
+        for (pixel, index) in img.pixels().zip(indices.iter()) {
+            assert_eq!(palette.colours[*index as usize], normalise(pixel.0));
+        }
+    }
+
+    #[test]
+    fn full_palette_with_a_transparent_colour_stays_exact() {
+        // 255 opaque colours and a transparent one in the sprites, with an empty
+        // border around them
+        let mut img = RgbaImage::new(18, 18);
+        for i in 0..256u32 {
+            let colour = match i {
+                255 => image::Rgba([12, 34, 56, 0]),
+                _ => image::Rgba([i as u8, 0, 0, 255]),
+            };
+            img.put_pixel(1 + i % 16, 1 + i / 16, colour);
+        }
+        let mut palette = Palette::exact(&img).unwrap();
+        assert_eq!(palette.colours.len(), 256);
+
+        let indices = index_pixels(&img, &mut palette, false);
+        for (pixel, index) in img.pixels().zip(indices.iter()) {
+            assert_eq!(palette.colours[*index as usize], normalise(pixel.0));
+        }
+    }
+
+    #[test]
+    fn full_palette_with_padding_stays_nearly_exact() {
+        // 256 opaque colours with transparent gaps, the last colour in a single pixel
+        let mut img = RgbaImage::new(36, 18);
+        for i in 0..256u32 {
+            let (x, y) = (1 + i % 16, 1 + i / 16);
+            if i == 255 {
+                img.put_pixel(x, y, image::Rgba([254, 0, 1, 255]));
+            } else {
+                img.put_pixel(x, y, image::Rgba([i as u8, 0, 0, 255]));
+                img.put_pixel(x + 18, y, image::Rgba([i as u8, 0, 0, 255]));
+            }
+        }
+        let mut palette = Palette::exact(&img).unwrap();
+        assert_eq!(palette.colours.len(), 256);
+        assert_eq!(
+            palette.colours[palette.transparent_index() as usize],
+            TRANSPARENT
+        );
+
+        let indices = index_pixels(&img, &mut palette, false);
+        let remapped = img
+            .pixels()
+            .zip(indices.iter())
+            .filter(|(pixel, index)| palette.colours[**index as usize] != normalise(pixel.0))
+            .count();
+        assert_eq!(remapped, 1);
+    }
+
+    #[test]
+    fn quantized_palette_fits() -> Result<(), Box<dyn Error>> {
+        let img = gradient(64, 64);
+        assert!(Palette::exact(&img).is_none());
+        let mut palette = Palette::quantize(&img)?;
+        assert!(palette.colours.len() <= 256);
+        let indices = index_pixels(&img, &mut palette, true);
+        assert_eq!(indices.len(), 64 * 64);
+        Ok(())
+    }
+
+    #[test]
+    fn forced_palette_gains_transparency() -> Result<(), Box<dyn Error>> {
+        let palette = Palette::new(vec![[255, 0, 0, 255]])?.with_transparency()?;
+        assert_eq!(palette.colours, vec![TRANSPARENT, [255, 0, 0, 255]]);
+        assert_eq!(palette.transparent_index(), 0);
+        Ok(())
+    }
+}
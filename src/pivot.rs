@@ -0,0 +1,77 @@
+use std::error::Error;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::meta_data::Pivot;
+use crate::sidecar;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Unit {
+    #[default]
+    Normalized,
+    Pixels,
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+pub struct PivotSidecar {
+    x: f32,
+    y: f32,
+    #[serde(default)]
+    unit: Unit,
+}
+
+impl PivotSidecar {
+    // normalized pivots follow the size of each sprite cut from the file
+    pub fn resolve(&self, width: u32, height: u32) -> Pivot {
+        match self.unit {
+            Unit::Normalized => Pivot {
+                x: self.x * width as f32,
+                y: self.y * height as f32,
+            },
+            Unit::Pixels => Pivot {
+                x: self.x,
+                y: self.y,
+            },
+        }
+    }
+}
+
+fn parse(path: &Path) -> Result<PivotSidecar, Box<dyn Error>> {
+    let sidecar: PivotSidecar = sidecar::read(path)?;
+    if !sidecar.x.is_finite() || !sidecar.y.is_finite() {
+        return Err(format!("{} needs a finite pivot", path.display()).into());
+    }
+    Ok(sidecar)
+}
+
+pub fn from_sidecar(path: &Path) -> Result<Option<PivotSidecar>, Box<dyn Error>> {
+    sidecar::path_for(path, "pivot")
+        .map(|sidecar| parse(&sidecar))
+        .transpose()
+}
+
+// the default for every image in a directory
+pub fn folder_default(path: &Path) -> Result<Option<PivotSidecar>, Box<dyn Error>> {
+    sidecar::folder_path_for(path, "pivot")
+        .map(|sidecar| parse(&sidecar))
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalized_and_pixel_pivots() -> Result<(), Box<dyn Error>> {
+        let feet: PivotSidecar = toml::from_str("x = 0.5\ny = 1.0")?;
+        assert_eq!(feet.resolve(31, 48), Pivot { x: 15.5, y: 48.0 });
+
+        let pixels: PivotSidecar = serde_json::from_str(r#"{"x": 3, "y": 4, "unit": "pixels"}"#)?;
+        assert_eq!(pixels.resolve(31, 48), Pivot { x: 3.0, y: 4.0 });
+
+        assert!(toml::from_str::<PivotSidecar>("x = 0.5\ny = 1.0\nunit = \"inches\"").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,200 @@
+use std::error::Error;
+use std::fs;
+
+use image::{ImageEncoder, RgbaImage};
+
+use crate::meta_data::SpriteData;
+
+const TEMPLATE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>__TITLE__</title>
+<style>
+body { font-family: sans-serif; background: #333; color: #eee; margin: 16px; }
+#controls { margin-bottom: 12px; }
+#controls > * { margin-right: 12px; }
+#sheet { position: relative; display: inline-block; background: repeating-conic-gradient(#666 0 25%, #555 0 50%) 0 0 / 16px 16px; }
+#sheet img { display: block; image-rendering: pixelated; }
+.sprite { position: absolute; box-sizing: border-box; border: 1px solid rgba(0, 255, 0, 0.6); }
+.sprite:hover { border-color: #0f0; background: rgba(0, 255, 0, 0.2); }
+.sprite.hidden { display: none; }
+.padding { position: absolute; box-sizing: border-box; border: 1px dashed #f0f; pointer-events: none; display: none; }
+#sheet.show-padding .padding { display: block; }
+#info { position: fixed; pointer-events: none; background: #000; padding: 4px 8px; white-space: pre; display: none; }
+</style>
+</head>
+<body>
+<div id="controls">
+<input id="search" type="search" placeholder="Filter by name">
+<label><input id="show-padding" type="checkbox"> Show padding (__PADDING__px) and extrusion (0px)</label>
+<span id="count"></span>
+</div>
+<div id="sheet"><img src="data:image/png;base64,__IMAGE__" width="__WIDTH__" height="__HEIGHT__"></div>
+<div id="info"></div>
+<script>
+const sprites = __SPRITES__;
+const padding = __PADDING__;
+const sheet = document.getElementById("sheet");
+const info = document.getElementById("info");
+const count = document.getElementById("count");
+const elements = sprites.map(sprite => {
+  const outer = document.createElement("div");
+  outer.className = "padding";
+  Object.assign(outer.style, {
+    left: (sprite.x - padding) + "px", top: (sprite.y - padding) + "px",
+    width: (sprite.width + padding * 2) + "px", height: (sprite.height + padding * 2) + "px",
+  });
+  const element = document.createElement("div");
+  element.className = "sprite";
+  Object.assign(element.style, {
+    left: sprite.x + "px", top: sprite.y + "px",
+    width: sprite.width + "px", height: sprite.height + "px",
+  });
+  element.addEventListener("mousemove", event => {
+    info.textContent = sprite.name + "\nx " + sprite.x + ", y " + sprite.y +
+      "\n" + sprite.width + "x" + sprite.height + "\nnot trimmed";
+    info.style.left = (event.clientX + 12) + "px";
+    info.style.top = (event.clientY + 12) + "px";
+    info.style.display = "block";
+  });
+  element.addEventListener("mouseleave", () => info.style.display = "none");
+  sheet.appendChild(outer);
+  sheet.appendChild(element);
+  return [sprite, element, outer];
+});
+function filter() {
+  const search = document.getElementById("search").value.toLowerCase();
+  let shown = 0;
+  for (const [sprite, element, outer] of elements) {
+    const hidden = !sprite.name.toLowerCase().includes(search);
+    element.classList.toggle("hidden", hidden);
+    outer.classList.toggle("hidden", hidden);
+    shown += hidden ? 0 : 1;
+  }
+  count.textContent = shown + " of " + sprites.length + " sprites";
+}
+document.getElementById("search").addEventListener("input", filter);
+document.getElementById("show-padding").addEventListener("change", event =>
+  sheet.classList.toggle("show-padding", event.target.checked));
+filter();
+</script>
+</body>
+</html>
+"#;
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64(bytes: &[u8]) -> String {
+    let mut result = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                result.push(BASE64_ALPHABET[(n >> (18 - i * 6) & 63) as usize] as char);
+            } else {
+                result.push('=');
+            }
+        }
+    }
+    result
+}
+
+fn sprites_json(sprite_data: &[SpriteData]) -> String {
+    let sprites = sprite_data
+        .iter()
+        .map(|sd| {
+            // the area covered in the sheet, turned for rotated sprites
+            let bb = sd.bounding_box();
+            format!(
+                "{{\"height\":{},\"name\":{},\"width\":{},\"x\":{},\"y\":{}}}",
+                bb.height,
+                serde_json::Value::from(sd.name.as_str()),
+                bb.width,
+                bb.x,
+                bb.y
+            )
+        })
+        .collect::<Vec<String>>()
+        .join(",\n");
+    // keep a sprite called </script> from ending the script early
+    format!("[\n{sprites}\n]").replace("</", "<\\/")
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+pub fn format(
+    title: &str,
+    sheet: &RgbaImage,
+    sprite_data: &[SpriteData],
+    padding: u32,
+) -> Result<String, Box<dyn Error>> {
+    let mut png = vec![];
+    image::codecs::png::PngEncoder::new(&mut png).write_image(
+        sheet.as_raw(),
+        sheet.width(),
+        sheet.height(),
+        image::ColorType::Rgba8,
+    )?;
+    Ok(TEMPLATE
+        .replace("__TITLE__", &html_escape(title))
+        .replace("__WIDTH__", &sheet.width().to_string())
+        .replace("__HEIGHT__", &sheet.height().to_string())
+        .replace("__PADDING__", &padding.to_string())
+        .replace("__SPRITES__", &sprites_json(sprite_data))
+        .replace("__IMAGE__", &base64(&png)))
+}
+
+pub fn write(
+    html_file: &str,
+    title: &str,
+    sheet: &RgbaImage,
+    sprite_data: &[SpriteData],
+    padding: u32,
+) -> Result<(), Box<dyn Error>> {
+    fs::write(html_file, format(title, sheet, sprite_data, padding)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base64_padding() {
+        assert_eq!(base64(b"Man"), "TWFu");
+        assert_eq!(base64(b"Ma"), "TWE=");
+        assert_eq!(base64(b"M"), "TQ==");
+        assert_eq!(base64(b""), "");
+    }
+
+    #[test]
+    fn embeds_sprites_and_image() -> Result<(), Box<dyn Error>> {
+        let sprite = SpriteData {
+            name: "say \"hi\"</script>".to_owned(),
+            x: 2,
+            y: 2,
+            width: 1,
+            height: 1,
+            ..Default::default()
+        };
+        let html = format("<sheet>", &RgbaImage::new(5, 5), &[sprite], 2)?;
+        assert!(html.contains("<title>&lt;sheet&gt;</title>"));
+        assert!(html.contains(
+            "{\"height\":1,\"name\":\"say \\\"hi\\\"<\\/script>\",\"width\":1,\"x\":2,\"y\":2}"
+        ));
+        assert!(html.contains("src=\"data:image/png;base64,iVBORw0KGgo"));
+        assert!(!html.contains("__"));
+        Ok(())
+    }
+}
@@ -0,0 +1,175 @@
+use std::error::Error;
+use std::fs;
+use std::time::Duration;
+
+use crate::meta_data::{json_name, SpriteData};
+
+const LARGEST_SPRITES: usize = 5;
+
+#[derive(Default)]
+pub struct Timings {
+    pub decode: Duration,
+    pub pack: Duration,
+    pub encode: Duration,
+}
+
+pub struct Report {
+    sprites: usize,
+    width: u32,
+    height: u32,
+    used_area: u64,
+    padding_area: u64,
+    largest: Vec<(String, u32, u32)>,
+    timings: Timings,
+}
+
+impl Report {
+    // `padded_area` is the area of the tree leaves the sprites were packed into,
+    // each sprite plus its padding and alignment
+    pub fn new(
+        sprite_data: &[SpriteData],
+        width: u32,
+        height: u32,
+        padding: u32,
+        padded_area: u64,
+        timings: Timings,
+    ) -> Report {
+        let used_area: u64 = sprite_data
+            .iter()
+            .map(|sd| sd.width as u64 * sd.height as u64)
+            .sum();
+        // the sheet also keeps a strip of padding along its top and left edges
+        let edge_area = width as u64 * height as u64
+            - width.saturating_sub(padding) as u64 * height.saturating_sub(padding) as u64;
+
+        let mut largest: Vec<(String, u32, u32)> = sprite_data
+            .iter()
+            .map(|sd| (sd.name.to_owned(), sd.width, sd.height))
+            .collect();
+        largest.sort_by(|a, b| {
+            (b.1 as u64 * b.2 as u64)
+                .cmp(&(a.1 as u64 * a.2 as u64))
+                .then_with(|| a.0.cmp(&b.0))
+        });
+        largest.truncate(LARGEST_SPRITES);
+
+        Report {
+            sprites: sprite_data.len(),
+            width,
+            height,
+            used_area,
+            padding_area: padded_area.saturating_sub(used_area) + edge_area,
+            largest,
+            timings,
+        }
+    }
+
+    fn total_area(&self) -> u64 {
+        self.width as u64 * self.height as u64
+    }
+
+    fn occupancy(&self) -> f64 {
+        match self.total_area() {
+            0 => 0.0,
+            total_area => self.used_area as f64 / total_area as f64,
+        }
+    }
+
+    pub fn print(&self) {
+        println!(
+            "Packed {} sprites into a {}x{} sheet",
+            self.sprites, self.width, self.height
+        );
+        println!(
+            "  occupancy: {:.1}% ({} of {} pixels)",
+            self.occupancy() * 100.0,
+            self.used_area,
+            self.total_area()
+        );
+        println!("  lost to padding: {} pixels", self.padding_area);
+        // corral neither trims nor aliases sprites, so nothing is saved by either
+        println!("  saved by trimming: 0 pixels, saved by aliasing: 0 pixels");
+        let largest = self
+            .largest
+            .iter()
+            .map(|(name, width, height)| format!("{name} {width}x{height}"))
+            .collect::<Vec<String>>()
+            .join(", ");
+        println!("  largest: {largest}");
+        println!(
+            "  decode {:.1}ms, pack {:.1}ms, encode {:.1}ms",
+            milliseconds(self.timings.decode),
+            milliseconds(self.timings.pack),
+            milliseconds(self.timings.encode)
+        );
+    }
+
+    fn to_json_string(&self) -> String {
+        let largest = self
+            .largest
+            .iter()
+            .map(|(name, width, height)| {
+                format!(
+                    "{{\"height\":{height},\"name\":\"{}\",\"width\":{width}}}",
+                    json_name(name)
+                )
+            })
+            .collect::<Vec<String>>()
+            .join(",");
+        let fields = [
+            "\"aliasing_saved_area\":0".to_owned(),
+            format!("\"decode_ms\":{:.3}", milliseconds(self.timings.decode)),
+            format!("\"encode_ms\":{:.3}", milliseconds(self.timings.encode)),
+            format!("\"height\":{}", self.height),
+            format!("\"largest\":[{largest}]"),
+            format!("\"occupancy\":{:.4}", self.occupancy()),
+            format!("\"pack_ms\":{:.3}", milliseconds(self.timings.pack)),
+            format!("\"padding_area\":{}", self.padding_area),
+            format!("\"sprites\":{}", self.sprites),
+            format!("\"total_area\":{}", self.total_area()),
+            "\"trimming_saved_area\":0".to_owned(),
+            format!("\"used_area\":{}", self.used_area),
+            format!("\"width\":{}", self.width),
+        ];
+        format!("{{{}}}\n", fields.join(","))
+    }
+
+    pub fn write(&self, report_file: &str) -> Result<(), Box<dyn Error>> {
+        fs::write(report_file, self.to_json_string())?;
+        Ok(())
+    }
+}
+
+fn milliseconds(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sprite(name: &str, width: u32, height: u32) -> SpriteData {
+        SpriteData {
+            name: name.to_owned(),
+            width,
+            height,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn areas_and_largest() {
+        // 8x4 and 4x4 sprites with 2px padding packed side by side into an 18x8 sheet
+        let sprites = [sprite("small", 4, 4), sprite("big one", 8, 4)];
+        let report = Report::new(&sprites, 18, 8, 2, 10 * 6 + 6 * 6, Timings::default());
+        assert_eq!(report.used_area, 48);
+        assert_eq!(report.padding_area, 96 - 48 + 18 * 8 - 16 * 6);
+        assert_eq!(report.occupancy(), 48.0 / 144.0);
+        assert_eq!(report.largest[0].0, "big one");
+        let json = report.to_json_string();
+        assert!(json.contains(
+            "\"largest\":[{\"height\":4,\"name\":\"big_one\",\"width\":8},{\"height\":4,\"name\":\"small\",\"width\":4}]"
+        ));
+        assert!(json.contains("\"occupancy\":0.3333,"));
+    }
+}
@@ -0,0 +1,68 @@
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::de::DeserializeOwned;
+
+// kinds of sidecar that may be written as json as well as toml
+const KINDS: [&str; 2] = ["pivot", "borders"];
+// kinds that can also describe every image in a directory
+const FOLDER_KINDS: [&str; 1] = ["pivot"];
+
+fn first_existing(candidates: [PathBuf; 2]) -> Option<PathBuf> {
+    candidates.into_iter().find(|candidate| candidate.exists())
+}
+
+// hero.png is described by hero.<kind>.toml or hero.<kind>.json next to it
+pub fn path_for(path: &Path, kind: &str) -> Option<PathBuf> {
+    let stem = path.file_prefix()?.to_string_lossy();
+    first_existing(
+        ["toml", "json"].map(|extension| path.with_file_name(format!("{stem}.{kind}.{extension}"))),
+    )
+}
+
+// <kind>.toml or <kind>.json describes every image in the directory of path
+pub fn folder_path_for(path: &Path, kind: &str) -> Option<PathBuf> {
+    let dir = path.parent()?;
+    first_existing(["toml", "json"].map(|extension| dir.join(format!("{kind}.{extension}"))))
+}
+
+// json sidecars sit among the images, so they are picked out by name while
+// every toml file is skipped
+pub fn is_sidecar(path: &Path) -> bool {
+    if path.extension() == Some("toml".as_ref()) {
+        return true;
+    }
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    KINDS
+        .iter()
+        .any(|kind| name.ends_with(&format!(".{kind}.json")))
+        || FOLDER_KINDS
+            .iter()
+            .any(|kind| name == format!("{kind}.json"))
+}
+
+pub fn read<T: DeserializeOwned>(path: &Path) -> Result<T, Box<dyn Error>> {
+    let text = fs::read_to_string(path)?;
+    if path.extension() == Some("json".as_ref()) {
+        Ok(serde_json::from_str(&text)?)
+    } else {
+        Ok(toml::from_str(&text)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognises_sidecars() {
+        assert!(is_sidecar(Path::new("chars/pivot.json")));
+        assert!(is_sidecar(Path::new("chars/pivot.toml")));
+        assert!(is_sidecar(Path::new("chars/hero.pivot.json")));
+        assert!(is_sidecar(Path::new("ui/button.borders.json")));
+        assert!(is_sidecar(Path::new("ui/animations.toml")));
+        assert!(!is_sidecar(Path::new("chars/hero.json")));
+        assert!(!is_sidecar(Path::new("ui/borders.json")));
+    }
+}
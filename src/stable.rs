@@ -0,0 +1,184 @@
+use crate::bounding_box::BoundingBox;
+
+fn overlaps(a: &BoundingBox, b: &BoundingBox) -> bool {
+    a.x < b.x + b.width && b.x < a.x + a.width && a.y < b.y + b.height && b.y < a.y + a.height
+}
+
+fn contains(outer: &BoundingBox, inner: &BoundingBox) -> bool {
+    outer.x <= inner.x
+        && outer.y <= inner.y
+        && inner.x as u64 + inner.width as u64 <= outer.x as u64 + outer.width as u64
+        && inner.y as u64 + inner.height as u64 <= outer.y as u64 + outer.height as u64
+}
+
+// the largest free rects left around `taken` inside `free`, at most one on
+// each side of it
+fn carve(free: &BoundingBox, taken: &BoundingBox) -> Vec<BoundingBox> {
+    let (free_right, free_bottom) = (free.x + free.width, free.y + free.height);
+    let (taken_right, taken_bottom) = (taken.x + taken.width, taken.y + taken.height);
+    let mut pieces = vec![];
+    if taken.x > free.x {
+        pieces.push(BoundingBox {
+            width: taken.x - free.x,
+            ..*free
+        });
+    }
+    if taken_right < free_right {
+        pieces.push(BoundingBox {
+            x: taken_right,
+            width: free_right - taken_right,
+            ..*free
+        });
+    }
+    if taken.y > free.y {
+        pieces.push(BoundingBox {
+            height: taken.y - free.y,
+            ..*free
+        });
+    }
+    if taken_bottom < free_bottom {
+        pieces.push(BoundingBox {
+            y: taken_bottom,
+            height: free_bottom - taken_bottom,
+            ..*free
+        });
+    }
+    pieces
+}
+
+// the empty space inside the sheet as the maximal free rects, which may
+// overlap. Any rect clear of everything taken lies inside one of them, so
+// placing and checking a sprite only looks at the free rects rather than at
+// every sprite taken so far
+pub struct FreeSpace {
+    free: Vec<BoundingBox>,
+}
+
+impl FreeSpace {
+    pub fn new(bounds: (u32, u32)) -> Self {
+        let free = BoundingBox {
+            x: 0,
+            y: 0,
+            width: bounds.0,
+            height: bounds.1,
+        };
+        FreeSpace {
+            free: if free.width > 0 && free.height > 0 {
+                vec![free]
+            } else {
+                vec![]
+            },
+        }
+    }
+
+    // rects kept where they were must lie inside the bounds without
+    // overlapping anything taken
+    pub fn is_free(&self, rect: &BoundingBox) -> bool {
+        self.free.iter().any(|free| contains(free, rect))
+    }
+
+    // the top-most, then left-most spot for a `width` x `height` rect. A spot
+    // inside a free rect slides up and left to the free rect's corner
+    pub fn find_spot(&self, width: u32, height: u32) -> Option<(u32, u32)> {
+        self.free
+            .iter()
+            .filter(|free| free.can_contain(width, height))
+            .map(|free| (free.y, free.x))
+            .min()
+            .map(|(y, x)| (x, y))
+    }
+
+    pub fn take(&mut self, rect: &BoundingBox) {
+        if rect.width == 0 || rect.height == 0 {
+            return;
+        }
+        let (cut, kept): (Vec<BoundingBox>, Vec<BoundingBox>) =
+            self.free.iter().partition(|free| overlaps(free, rect));
+        let pieces: Vec<BoundingBox> = cut.iter().flat_map(|free| carve(free, rect)).collect();
+        // a rect the cut left whole can't lie inside a new piece, as each piece
+        // lies inside a rect that was already maximal
+        let mut maximal: Vec<BoundingBox> = vec![];
+        for (i, piece) in pieces.iter().enumerate() {
+            let inside_kept = kept.iter().any(|free| contains(free, piece));
+            let inside_piece = pieces
+                .iter()
+                .enumerate()
+                .any(|(j, other)| j != i && contains(other, piece) && (other != piece || j < i));
+            if !inside_kept && !inside_piece {
+                maximal.push(*piece);
+            }
+        }
+        self.free = kept;
+        self.free.extend(maximal);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(x: u32, y: u32, width: u32, height: u32) -> BoundingBox {
+        BoundingBox {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    fn taking(bounds: (u32, u32), taken: &[BoundingBox]) -> FreeSpace {
+        let mut space = FreeSpace::new(bounds);
+        for rect in taken {
+            space.take(rect);
+        }
+        space
+    }
+
+    #[test]
+    fn finds_the_top_left_gap() {
+        // two 4x4 rects in an 8x8 area leave the bottom row free
+        let space = taking((8, 8), &[rect(0, 0, 4, 4), rect(4, 0, 4, 4)]);
+        assert_eq!(space.find_spot(4, 4), Some((0, 4)));
+        assert_eq!(space.find_spot(8, 4), Some((0, 4)));
+        assert_eq!(space.find_spot(8, 5), None);
+
+        // a hole left by a removed sprite
+        let space = taking(
+            (8, 8),
+            &[rect(0, 0, 4, 4), rect(0, 4, 4, 4), rect(4, 4, 4, 4)],
+        );
+        assert_eq!(space.find_spot(4, 4), Some((4, 0)));
+        assert_eq!(space.find_spot(2, 2), Some((4, 0)));
+
+        // free space around a rect in the middle joins up on every side
+        let space = taking((8, 8), &[rect(2, 2, 4, 4)]);
+        assert_eq!(space.find_spot(8, 2), Some((0, 0)));
+        assert_eq!(space.find_spot(2, 8), Some((0, 0)));
+        assert_eq!(space.find_spot(3, 3), None);
+        assert_eq!(space.free.len(), 4);
+    }
+
+    #[test]
+    fn kept_rects_stay_apart_and_inside() {
+        let space = taking((8, 8), &[rect(0, 0, 4, 4)]);
+        assert!(space.is_free(&rect(4, 0, 4, 4)));
+        assert!(!space.is_free(&rect(3, 0, 4, 4)));
+        assert!(!space.is_free(&rect(6, 0, 4, 4)));
+    }
+
+    #[test]
+    fn free_space_tracks_many_rects() {
+        // a 64x64 grid of 2x2 rects with every other one left out
+        let mut space = FreeSpace::new((128, 128));
+        for y in 0..64 {
+            for x in 0..64 {
+                if (x + y) % 2 == 0 {
+                    space.take(&rect(x * 2, y * 2, 2, 2));
+                }
+            }
+        }
+        assert_eq!(space.free.len(), 64 * 32);
+        assert_eq!(space.find_spot(2, 2), Some((2, 0)));
+        assert_eq!(space.find_spot(3, 2), None);
+    }
+}
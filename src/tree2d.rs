@@ -1,3 +1,4 @@
+use std::collections::{BTreeMap, BTreeSet};
 use std::error::Error;
 
 use crate::bounding_box::BoundingBox;
@@ -21,32 +22,51 @@ impl std::fmt::Display for InsertionError {
 
 type Handle = usize;
 
+// the handle `Tree2d::insert` returns, it keeps naming the same data until the
+// data is removed, however the tree is rearranged in the meantime
+pub type DataHandle = usize;
+
+// slots freed by `take` are reused by later calls to `store`
 struct Arena<T> {
-    data: Vec<T>,
-    next_handle: usize,
+    data: Vec<Option<T>>,
+    vacant: Vec<Handle>,
 }
 
 impl<T> Arena<T> {
     fn get(&self, handle: Handle) -> Option<&T> {
-        if self.data.len() < handle || self.next_handle <= handle {
-            None
-        } else {
-            Some(&self.data[handle])
-        }
+        self.data.get(handle).and_then(Option::as_ref)
     }
 
     fn get_mut(&mut self, handle: Handle) -> Option<&mut T> {
-        if self.data.len() < handle {
-            None
-        } else {
-            Some(&mut self.data[handle])
-        }
+        self.data.get_mut(handle).and_then(Option::as_mut)
     }
 
     fn store(&mut self, data: T) -> Handle {
-        self.data.push(data);
-        self.next_handle += 1;
-        self.next_handle - 1
+        match self.vacant.pop() {
+            Some(handle) => {
+                self.data[handle] = Some(data);
+                handle
+            }
+            None => {
+                self.data.push(Some(data));
+                self.data.len() - 1
+            }
+        }
+    }
+
+    fn take(&mut self, handle: Handle) -> Option<T> {
+        let data = self.data.get_mut(handle).and_then(Option::take);
+        if data.is_some() {
+            self.vacant.push(handle);
+        }
+        data
+    }
+
+    fn iter(&self) -> impl Iterator<Item = (Handle, &T)> {
+        self.data
+            .iter()
+            .enumerate()
+            .filter_map(|(handle, data)| data.as_ref().map(|data| (handle, data)))
     }
 }
 
@@ -54,7 +74,182 @@ impl<T> Arena<T> {
     fn new() -> Self {
         Arena {
             data: Vec::new(),
-            next_handle: 0,
+            vacant: Vec::new(),
+        }
+    }
+}
+
+// leaves ordered by width and by height, so the narrowest and the shortest leaf
+// that can hold some data are each found with a range query. Between them they
+// leave the least space along either side
+#[derive(Default)]
+struct SizeIndex {
+    by_width: BTreeSet<(u32, u32, Handle)>,
+    by_height: BTreeSet<(u32, u32, Handle)>,
+}
+
+impl SizeIndex {
+    fn insert(&mut self, handle: Handle, bb: BoundingBox) {
+        self.by_width.insert((bb.width, bb.height, handle));
+        self.by_height.insert((bb.height, bb.width, handle));
+    }
+
+    fn remove(&mut self, handle: Handle, bb: BoundingBox) {
+        self.by_width.remove(&(bb.width, bb.height, handle));
+        self.by_height.remove(&(bb.height, bb.width, handle));
+    }
+
+    // the first leaf at least `major` along the side the leaves are ordered by
+    // and `minor` along the other, skipping to the next major size whenever
+    // the rest of one are too small
+    fn first_fit(leaves: &BTreeSet<(u32, u32, Handle)>, major: u32, minor: u32) -> Option<Handle> {
+        let mut from = (major, minor, 0);
+        while let Some(&(leaf_major, leaf_minor, handle)) = leaves.range(from..).next() {
+            if leaf_minor >= minor {
+                return Some(handle);
+            }
+            from = (leaf_major, minor, 0);
+        }
+        None
+    }
+
+    fn fits(&self, width: u32, height: u32) -> impl Iterator<Item = Handle> {
+        Self::first_fit(&self.by_width, width, height)
+            .into_iter()
+            .chain(Self::first_fit(&self.by_height, height, width))
+    }
+
+    fn handles(&self) -> impl Iterator<Item = Handle> + '_ {
+        self.by_width.iter().map(|&(_, _, handle)| handle)
+    }
+}
+
+// free leaves with some area. Edges short of the root's always lie on an edge
+// of some data, so a leaf short of them both ways sits inside the total bounding
+// box and one short of the bottom never reaches below it. Leaves reaching the
+// right edge are keyed by x and those reaching the bottom by y, as where they
+// start is all that decides how much they would grow the total bounding box.
+// Leaves sharing a key are all as wide, or as tall, so they are ordered by the
+// other side. Leaves don't overlap, so only one can hold the corner
+struct LeafIndex {
+    right: u64,
+    bottom: u64,
+    inside: SizeIndex,
+    right_edge: BTreeMap<u32, BTreeSet<(u32, Handle)>>,
+    bottom_edge: BTreeMap<u32, BTreeSet<(u32, Handle)>>,
+    corner: BTreeSet<Handle>,
+}
+
+impl LeafIndex {
+    fn new(root: BoundingBox) -> Self {
+        LeafIndex {
+            right: root.x as u64 + root.width as u64,
+            bottom: root.y as u64 + root.height as u64,
+            inside: SizeIndex::default(),
+            right_edge: BTreeMap::new(),
+            bottom_edge: BTreeMap::new(),
+            corner: BTreeSet::new(),
+        }
+    }
+
+    fn insert(&mut self, handle: Handle, bb: BoundingBox) {
+        if bb.width == 0 || bb.height == 0 {
+            return;
+        }
+        match self.edges(bb) {
+            (false, false) => self.inside.insert(handle, bb),
+            (true, false) => {
+                self.right_edge
+                    .entry(bb.x)
+                    .or_default()
+                    .insert((bb.height, handle));
+            }
+            (false, true) => {
+                self.bottom_edge
+                    .entry(bb.y)
+                    .or_default()
+                    .insert((bb.width, handle));
+            }
+            (true, true) => {
+                self.corner.insert(handle);
+            }
+        };
+    }
+
+    fn remove(&mut self, handle: Handle, bb: BoundingBox) {
+        match self.edges(bb) {
+            (false, false) => self.inside.remove(handle, bb),
+            (true, false) => Self::remove_keyed(&mut self.right_edge, bb.x, (bb.height, handle)),
+            (false, true) => Self::remove_keyed(&mut self.bottom_edge, bb.y, (bb.width, handle)),
+            (true, true) => {
+                self.corner.remove(&handle);
+            }
+        }
+    }
+
+    fn remove_keyed(
+        leaves: &mut BTreeMap<u32, BTreeSet<(u32, Handle)>>,
+        key: u32,
+        leaf: (u32, Handle),
+    ) {
+        if let Some(handles) = leaves.get_mut(&key) {
+            handles.remove(&leaf);
+            if handles.is_empty() {
+                leaves.remove(&key);
+            }
+        }
+    }
+
+    fn edges(&self, bb: BoundingBox) -> (bool, bool) {
+        (
+            bb.x as u64 + bb.width as u64 == self.right,
+            bb.y as u64 + bb.height as u64 == self.bottom,
+        )
+    }
+
+    fn all(&self) -> Vec<Handle> {
+        let keyed = |leaves: &BTreeSet<(u32, Handle)>| -> Vec<Handle> {
+            leaves.iter().map(|&(_, handle)| handle).collect()
+        };
+        let mut result: Vec<Handle> = self
+            .inside
+            .handles()
+            .chain(self.right_edge.values().flat_map(keyed))
+            .chain(self.bottom_edge.values().flat_map(keyed))
+            .chain(self.corner.iter().copied())
+            .collect();
+        result.sort_unstable();
+        result
+    }
+
+    // the shortest leaf at an x along the right edge, or the narrowest at a y
+    // along the bottom, at least `size` long
+    fn first_fit(leaves: &BTreeSet<(u32, Handle)>, size: u32) -> Option<Handle> {
+        leaves.range((size, 0)..).next().map(|&(_, handle)| handle)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LeafChoice {
+    MostSquare,
+    SmallestBoundingBox,
+    BestShortSide,
+}
+
+impl LeafChoice {
+    pub const ALL: [LeafChoice; 3] = [
+        LeafChoice::MostSquare,
+        LeafChoice::SmallestBoundingBox,
+        LeafChoice::BestShortSide,
+    ];
+}
+
+impl std::fmt::Display for LeafChoice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        match self {
+            LeafChoice::MostSquare => write!(f, "most-square"),
+            LeafChoice::SmallestBoundingBox => write!(f, "smallest-box"),
+            LeafChoice::BestShortSide => write!(f, "best-short-side"),
         }
     }
 }
@@ -65,25 +260,41 @@ pub struct DataSize {
     pub height: u32,
 }
 
+// where `defragment` moved the data behind a handle
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Move {
+    pub handle: DataHandle,
+    pub from: BoundingBox,
+    pub to: BoundingBox,
+}
+
 #[allow(dead_code)]
 pub struct Tree2d<T> {
     root: Handle,
     nodes: Arena<Node<T>>,
+    // the node holding the data behind each data handle
+    entries: Arena<Handle>,
+    leaf_choice: LeafChoice,
+    // covers every partition made so far, removing data doesn't shrink it
+    total_bb: BoundingBox,
+    free: LeafIndex,
 }
 
-#[allow(dead_code)]
 struct Node<T> {
     bb: BoundingBox,
     parent: Option<Handle>,
     link: Option<Link<T>>,
 }
 
-#[allow(dead_code)]
+// `data` is None once removed and the area it covered becomes the `freed`
+// leaf, the node then stays split until all three partitions are empty again
 struct Link<T> {
-    data: T,
+    data: Option<T>,
     data_bb: BoundingBox,
     down: Handle,
     right: Handle,
+    freed: Option<Handle>,
 }
 
 impl<T> Node<T> {
@@ -95,6 +306,7 @@ impl<T> Node<T> {
         }
     }
 
+    #[cfg(test)]
     fn is_leaf(&self) -> bool {
         match self.link {
             None => true,
@@ -104,100 +316,329 @@ impl<T> Node<T> {
 }
 
 impl<T> Tree2d<T> {
+    #[allow(dead_code)]
     pub fn new() -> Self {
+        Self::with_leaf_choice(LeafChoice::MostSquare)
+    }
+
+    pub fn with_leaf_choice(leaf_choice: LeafChoice) -> Self {
+        Self::with_bounds(leaf_choice, u32::MAX, u32::MAX)
+    }
+
+    // a tree that never grows past `width` x `height`
+    pub fn with_bounds(leaf_choice: LeafChoice, width: u32, height: u32) -> Self {
         let node: Node<T> = Node::new_leaf(
             BoundingBox {
                 x: 0,
                 y: 0,
-                width: u32::MAX,
-                height: u32::MAX,
+                width,
+                height,
             },
             None,
         );
 
+        let mut free = LeafIndex::new(node.bb);
+        let bb = node.bb;
         let mut nodes = Arena::new();
         let root = nodes.store(node);
-
-        Tree2d { root, nodes }
+        free.insert(root, bb);
+
+        Tree2d {
+            root,
+            nodes,
+            entries: Arena::new(),
+            leaf_choice,
+            total_bb: BoundingBox {
+                x: 0,
+                y: 0,
+                width: 0,
+                height: 0,
+            },
+            free,
+        }
     }
 
     pub fn get_total_bounding_box(&self) -> BoundingBox {
-        let mut result = BoundingBox {
-            x: 0,
-            y: 0,
-            width: 0,
-            height: 0,
-        };
-
-        for node in self.nodes.data.iter() {
-            if let Some(link) = &node.link {
-                result = result + link.data_bb
-            }
-        }
-
-        result
+        self.total_bb
     }
 
-    #[allow(dead_code)]
-    pub fn insert(&mut self, width: u32, height: u32, data: T) -> Result<(), Box<dyn Error>> {
-        let total_bb = self.get_total_bounding_box();
-        let handle = self.get_most_square_leaf_handle_for_data(total_bb, width, height);
+    pub fn insert(
+        &mut self,
+        width: u32,
+        height: u32,
+        data: T,
+    ) -> Result<DataHandle, Box<dyn Error>> {
+        let handle = self.get_leaf_handle_for_data(self.total_bb, width, height);
         match handle {
             None => Err(Box::new(InsertionError {
                 msg: "Error inserting data, no partition large enough".to_owned(),
             })),
             Some((handle, _)) => {
+                let entry = self.entries.store(handle);
                 self.partition(handle, data, width, height);
-                Ok(())
+                Ok(entry)
             }
         }
     }
 
+    #[allow(dead_code)]
     pub fn insert_all(&mut self, data: Vec<(DataSize, T)>) -> Result<(), Box<dyn Error>> {
-        // self.nodes.data.reserve(data.len() * 2);
-        let mut total_bb = self.get_total_bounding_box();
+        self.nodes.data.reserve(data.len() * 2);
         for (DataSize { width, height }, data) in data {
-            let handle = self.get_most_square_leaf_handle_for_data(total_bb, width, height);
-            match handle {
-                None => Err(Box::new(InsertionError {
-                    msg: "Error inserting data, no partition large enough".to_owned(),
-                })),
-                Some((handle, bb)) => {
-                    self.partition(handle, data, width, height);
-                    total_bb = bb;
-                    Ok(())
-                }
-            }?;
+            self.insert(width, height, data)?;
         }
         Ok(())
     }
 
+    // frees the data's partition, merging it with the partitions split off
+    // alongside it wherever all of them are empty
+    #[allow(dead_code)]
+    pub fn remove(&mut self, entry: DataHandle) -> Option<T> {
+        let handle = self.entries.take(entry)?;
+        let link = self.nodes.get_mut(handle)?.link.as_mut()?;
+        let data = link.data.take();
+        let data_bb = link.data_bb;
+        let freed = self.nodes.store(Node::new_leaf(data_bb, Some(handle)));
+        self.free.insert(freed, data_bb);
+        if let Some(link) = self
+            .nodes
+            .get_mut(handle)
+            .and_then(|node| node.link.as_mut())
+        {
+            link.freed = Some(freed);
+        }
+        self.merge(handle);
+        data
+    }
+
+    #[allow(dead_code)]
+    pub fn get(&self, entry: DataHandle) -> Option<(&T, BoundingBox)> {
+        let link = self.nodes.get(*self.entries.get(entry)?)?.link.as_ref()?;
+        link.data.as_ref().map(|data| (data, link.data_bb))
+    }
+
+    fn merge(&mut self, mut handle: Handle) {
+        loop {
+            let Some(node) = self.nodes.get(handle) else {
+                return;
+            };
+            let Some(link) = &node.link else {
+                return;
+            };
+            let is_free = |handle: Handle| {
+                self.nodes
+                    .get(handle)
+                    .is_some_and(|node| node.link.is_none())
+            };
+            let Some(freed) = link.freed.filter(|_| link.data.is_none()) else {
+                return;
+            };
+            if !is_free(freed) || !is_free(link.right) || !is_free(link.down) {
+                return;
+            }
+            let (right, down, parent, bb) = (link.right, link.down, node.parent, node.bb);
+            for child in [right, down, freed] {
+                if let Some(node) = self.nodes.take(child) {
+                    self.free.remove(child, node.bb);
+                }
+            }
+            if let Some(node) = self.nodes.get_mut(handle) {
+                node.link = None;
+            }
+            self.free.insert(handle, bb);
+            match parent {
+                Some(parent) => handle = parent,
+                None => return,
+            }
+        }
+    }
+
+    // repacks the data, largest first, and returns where each piece moved.
+    // Nothing moves unless the repacked tree has a smaller bounding box
+    #[allow(dead_code)]
+    pub fn defragment(&mut self) -> Vec<Move> {
+        let mut live: Vec<(DataHandle, BoundingBox)> = self
+            .entries
+            .iter()
+            .filter_map(|(entry, handle)| {
+                let link = self.nodes.get(*handle)?.link.as_ref()?;
+                Some((entry, link.data_bb))
+            })
+            .collect();
+        live.sort_by_key(|(entry, bb)| {
+            (
+                std::cmp::Reverse(bb.width as u64 * bb.height as u64),
+                std::cmp::Reverse(bb.height),
+                *entry,
+            )
+        });
+
+        let Some(root) = self.nodes.get(self.root).map(|root| root.bb) else {
+            return vec![];
+        };
+        let mut repacked =
+            Tree2d::<DataHandle>::with_bounds(self.leaf_choice, root.width, root.height);
+        for (entry, bb) in &live {
+            if repacked.insert(bb.width, bb.height, *entry).is_err() {
+                return vec![];
+            }
+        }
+        if repacked.total_bb >= self.total_bb {
+            return vec![];
+        }
+
+        let mut old_nodes = std::mem::replace(&mut self.nodes, Arena::new());
+        let mut moves = vec![];
+        for (handle, node) in repacked.nodes.data.into_iter().enumerate() {
+            let Some(node) = node else {
+                self.nodes.data.push(None);
+                self.nodes.vacant.push(handle);
+                continue;
+            };
+            let link = node.link.map(|link| {
+                let entry = link.data.unwrap_or_default();
+                let old = self
+                    .entries
+                    .get_mut(entry)
+                    .map(|old_handle| std::mem::replace(old_handle, handle));
+                let old_link = old
+                    .and_then(|old| old_nodes.get_mut(old))
+                    .and_then(|old_node| old_node.link.as_mut());
+                if let Some(old_link) = &old_link {
+                    if old_link.data_bb != link.data_bb {
+                        moves.push(Move {
+                            handle: entry,
+                            from: old_link.data_bb,
+                            to: link.data_bb,
+                        });
+                    }
+                }
+                Link {
+                    data: old_link.and_then(|old_link| old_link.data.take()),
+                    data_bb: link.data_bb,
+                    down: link.down,
+                    right: link.right,
+                    freed: None,
+                }
+            });
+            self.nodes.data.push(Some(Node {
+                bb: node.bb,
+                parent: node.parent,
+                link,
+            }));
+        }
+        self.root = repacked.root;
+        self.total_bb = repacked.total_bb;
+        self.free = repacked.free;
+        moves.sort_by_key(|m| m.handle);
+        moves
+    }
+
     pub fn flatten(&self) -> Vec<(&T, BoundingBox)> {
         let mut result = vec![];
-        for node in self.nodes.data.iter() {
-            match &node.link {
-                None => (),
-                Some(link) => result.push((&link.data, link.data_bb)),
-            };
+        for (_, node) in self.nodes.iter() {
+            if let Some(Link {
+                data: Some(data),
+                data_bb,
+                ..
+            }) = &node.link
+            {
+                result.push((data, *data_bb));
+            }
         }
         result
     }
 
-    fn leaves(&self) -> Vec<Handle> {
+    // the empty space left in the tree, the outermost leaves stretch to the edges
+    // of the root, u32::MAX unless the tree was given bounds
+    pub fn free_leaves(&self) -> Vec<BoundingBox> {
+        self.leaves()
+            .into_iter()
+            .filter_map(|handle| self.nodes.get(handle).map(|node| node.bb))
+            .collect()
+    }
+
+    // the right and down partitions cut from each node holding data, their top
+    // and left edges are the guillotine cuts
+    pub fn splits(&self) -> Vec<(BoundingBox, BoundingBox)> {
         let mut result = vec![];
-        for (i, node) in self.nodes.data.iter().enumerate() {
-            if node.is_leaf() {
-                result.push(i);
+        for (_, node) in self.nodes.iter() {
+            if let Some(link) = &node.link {
+                if let (Some(right), Some(down)) =
+                    (self.nodes.get(link.right), self.nodes.get(link.down))
+                {
+                    result.push((right.bb, down.bb));
+                }
             }
         }
         result
     }
 
-    #[allow(dead_code)]
-    fn get_smallest_leaf_handle_for_data(&mut self, width: u32, height: u32) -> Option<Handle> {
+    fn leaves(&self) -> Vec<Handle> {
+        self.free.all()
+    }
+
+    // the leaves that can hold the data, in handle order. Only the best leaf
+    // inside the total bounding box and at each position along its edges can
+    // score best, the others leave more space or grow it as much
+    fn candidate_leaves(&self, width: u32, height: u32) -> Vec<Handle> {
+        let fits = |handle: &Handle| {
+            self.nodes
+                .get(*handle)
+                .is_some_and(|node| node.bb.can_contain(width, height))
+        };
+        let mut result: Vec<Handle> = self
+            .free
+            .inside
+            .fits(width, height)
+            .chain(
+                self.free
+                    .right_edge
+                    .values()
+                    .filter_map(|leaves| LeafIndex::first_fit(leaves, height)),
+            )
+            .chain(
+                self.free
+                    .bottom_edge
+                    .values()
+                    .filter_map(|leaves| LeafIndex::first_fit(leaves, width)),
+            )
+            .chain(self.free.corner.iter().copied())
+            .filter(fits)
+            .collect();
+        result.sort_unstable();
+        result.dedup();
+        result
+    }
+
+    fn get_leaf_handle_for_data(
+        &self,
+        total_bb: BoundingBox,
+        width: u32,
+        height: u32,
+    ) -> Option<(Handle, BoundingBox)> {
+        match self.leaf_choice {
+            LeafChoice::MostSquare => {
+                self.get_most_square_leaf_handle_for_data(total_bb, width, height)
+            }
+            LeafChoice::SmallestBoundingBox => {
+                self.get_smallest_leaf_handle_for_data(total_bb, width, height)
+            }
+            LeafChoice::BestShortSide => {
+                self.get_best_short_side_leaf_handle_for_data(total_bb, width, height)
+            }
+        }
+    }
+
+    #[allow(clippy::unnecessary_sort_by)]
+    fn get_smallest_leaf_handle_for_data(
+        &self,
+        total_bb: BoundingBox,
+        width: u32,
+        height: u32,
+    ) -> Option<(Handle, BoundingBox)> {
         let mut leaves = vec![];
-        let total_bb = self.get_total_bounding_box();
-        for handle in self.leaves() {
+        for handle in self.candidate_leaves(width, height) {
             if let Some(node) = self.nodes.get(handle) {
                 if node.bb.can_contain(width, height) {
                     let bb = total_bb
@@ -216,19 +657,65 @@ impl<T> Tree2d<T> {
             None
         } else {
             leaves.sort_by(|a, b| a.0.cmp(&b.0));
-            Some(leaves[0].1)
+            Some((leaves[0].1, leaves[0].0))
         }
     }
 
+    // the leaf leaving the least space along its shorter side, counting only the
+    // part of the leaf inside the current bounding box. Leaves that would grow the
+    // bounding box come after every leaf that doesn't, smallest growth first
+    fn get_best_short_side_leaf_handle_for_data(
+        &self,
+        total_bb: BoundingBox,
+        width: u32,
+        height: u32,
+    ) -> Option<(Handle, BoundingBox)> {
+        let mut leaves = vec![];
+        for handle in self.candidate_leaves(width, height) {
+            if let Some(node) = self.nodes.get(handle) {
+                if node.bb.can_contain(width, height) {
+                    let bb = total_bb
+                        + BoundingBox {
+                            x: node.bb.x,
+                            y: node.bb.y,
+                            width,
+                            height,
+                        };
+                    let score = if bb == total_bb {
+                        let inside_width = (total_bb.x + total_bb.width)
+                            .min(node.bb.x.saturating_add(node.bb.width))
+                            - node.bb.x;
+                        let inside_height = (total_bb.y + total_bb.height)
+                            .min(node.bb.y.saturating_add(node.bb.height))
+                            - node.bb.y;
+                        let (width_left, height_left) =
+                            (inside_width - width, inside_height - height);
+                        (
+                            false,
+                            width_left.min(height_left) as u64,
+                            width_left.max(height_left) as u64,
+                        )
+                    } else {
+                        (true, bb.area() as u64, bb.perimeter() as u64)
+                    };
+                    leaves.push((score, handle, bb));
+                }
+            }
+        }
+        leaves
+            .into_iter()
+            .min_by_key(|leaf| leaf.0)
+            .map(|leaf| (leaf.1, leaf.2))
+    }
+
     fn get_most_square_leaf_handle_for_data(
-        &mut self,
+        &self,
         total_bb: BoundingBox,
         width: u32,
         height: u32,
     ) -> Option<(Handle, BoundingBox)> {
         let mut leaves = vec![];
-        // let total_bb = self.get_total_bounding_box();
-        for handle in self.leaves() {
+        for handle in self.candidate_leaves(width, height) {
             if let Some(node) = self.nodes.get(handle) {
                 if node.bb.can_contain(width, height) {
                     let bb = total_bb
@@ -312,8 +799,11 @@ impl<T> Tree2d<T> {
         };
 
         if let (Some(right), Some(down)) = (right, down) {
+            let (right_bb, down_bb) = (right.bb, down.bb);
             let right_handle = self.nodes.store(right);
             let down_handle = self.nodes.store(down);
+            self.free.insert(right_handle, right_bb);
+            self.free.insert(down_handle, down_bb);
             if let Some(node) = self.nodes.get_mut(handle) {
                 let data_bb = BoundingBox {
                     x: node.bb.x,
@@ -321,16 +811,15 @@ impl<T> Tree2d<T> {
                     width,
                     height,
                 };
-                *node = Node {
-                    bb: node.bb,
-                    parent: None,
-                    link: Some(Link {
-                        data,
-                        data_bb,
-                        down: down_handle,
-                        right: right_handle,
-                    }),
-                }
+                self.free.remove(handle, node.bb);
+                self.total_bb = self.total_bb + data_bb;
+                node.link = Some(Link {
+                    data: Some(data),
+                    data_bb,
+                    down: down_handle,
+                    right: right_handle,
+                    freed: None,
+                });
             }
         }
     }
@@ -341,6 +830,7 @@ mod tree_2d_tests {
     use super::*;
 
     #[test]
+    #[allow(clippy::assertions_on_constants)]
     fn new_empty_tree() -> Result<(), Box<dyn Error>> {
         let tree = Tree2d::<u32>::new();
         let root_node = tree.nodes.get(tree.root);
@@ -417,6 +907,21 @@ mod tree_2d_tests {
         Ok(())
     }
 
+    #[test]
+    fn free_leaves_and_splits() -> Result<(), Box<dyn Error>> {
+        let mut tree = Tree2d::<u32>::new();
+        tree.partition(tree.root, 1, 4, 2);
+
+        let free_leaves = tree.free_leaves();
+        assert_eq!(free_leaves.len(), 2);
+        let (right, down) = tree.splits()[0];
+        assert_eq!(free_leaves, vec![right, down]);
+        assert_eq!((right.x, right.y, right.height), (4, 0, 2));
+        assert_eq!((down.x, down.y, down.width), (0, 2, u32::MAX));
+
+        Ok(())
+    }
+
     #[test]
     fn get_smallest_leaf_for_data() -> Result<(), Box<dyn Error>> {
         let mut tree = Tree2d::<u32>::new();
@@ -425,17 +930,31 @@ mod tree_2d_tests {
         let width = 2;
         let height = 1;
 
-        assert_eq!(
-            tree.get_smallest_leaf_handle_for_data(width, height),
-            Some(0)
-        );
+        let smallest = |tree: &Tree2d<u32>| {
+            tree.get_smallest_leaf_handle_for_data(tree.get_total_bounding_box(), width, height)
+                .map(|(handle, _)| handle)
+        };
+
+        assert_eq!(smallest(&tree), Some(0));
 
         tree.partition(tree.root, data, width, height);
 
-        assert_eq!(
-            tree.get_smallest_leaf_handle_for_data(width, height),
-            Some(2)
-        );
+        assert_eq!(smallest(&tree), Some(2));
+
+        Ok(())
+    }
+
+    #[test]
+    fn best_short_side_fills_holes() -> Result<(), Box<dyn Error>> {
+        let mut tree = Tree2d::<u32>::with_leaf_choice(LeafChoice::BestShortSide);
+
+        tree.insert(4, 4, 0)?;
+        tree.insert(2, 2, 1)?;
+        tree.insert(2, 2, 2)?;
+
+        // the second square fits in the hole beside the first without growing the box
+        let total_bb = tree.get_total_bounding_box();
+        assert_eq!((total_bb.width, total_bb.height), (6, 4));
 
         Ok(())
     }
@@ -481,20 +1000,141 @@ mod tree_2d_tests {
         Ok(())
     }
 
-    // #[test]
-    // fn one_million_insertions() -> Result<(), Box<dyn Error>> {
-    //     let mut tree = Tree2d::<u32>::new();
-    //     let data = vec![
-    //         (
-    //             DataSize {
-    //                 width: 1,
-    //                 height: 1
-    //             },
-    //             0
-    //         );
-    //         100_000
-    //     ];
-    //     tree.insert_all(data)?;
-    //     Ok(())
-    // }
+    fn four_by_four(x: u32, y: u32) -> BoundingBox {
+        BoundingBox {
+            x,
+            y,
+            width: 4,
+            height: 4,
+        }
+    }
+
+    #[test]
+    fn bounded_tree_fills_up() -> Result<(), Box<dyn Error>> {
+        let mut tree = Tree2d::<char>::with_bounds(LeafChoice::MostSquare, 8, 8);
+        for data in ['a', 'b', 'c', 'd'] {
+            tree.insert(4, 4, data)?;
+        }
+        assert_eq!(tree.get_total_bounding_box().area(), 64);
+        assert!(tree.insert(1, 1, 'e').is_err());
+
+        // leaves reaching the bounds can still grow the box, so both leaves are
+        // tried and the 4x4 box, with the smaller perimeter, wins over 8x2
+        let mut tree = Tree2d::<char>::with_bounds(LeafChoice::SmallestBoundingBox, 8, 8);
+        tree.insert(4, 2, 'a')?;
+        let b = tree.insert(4, 2, 'b')?;
+        assert_eq!(tree.get(b).map(|(_, bb)| (bb.x, bb.y)), Some((0, 2)));
+        Ok(())
+    }
+
+    #[test]
+    fn remove_merges_empty_partitions() -> Result<(), Box<dyn Error>> {
+        let mut tree = Tree2d::<char>::new();
+        let a = tree.insert(4, 4, 'a')?;
+        let b = tree.insert(4, 4, 'b')?;
+        let c = tree.insert(4, 4, 'c')?;
+        assert_eq!(tree.get(b), Some((&'b', four_by_four(4, 0))));
+
+        assert_eq!(tree.remove(b), Some('b'));
+        assert_eq!(tree.remove(b), None);
+        assert_eq!(tree.get(b), None);
+        assert_eq!(tree.get(a), Some((&'a', four_by_four(0, 0))));
+        assert_eq!(tree.get(c), Some((&'c', four_by_four(0, 4))));
+
+        // a's partition stays split while c is still in it, with a's area free
+        tree.remove(a);
+        assert_eq!(tree.flatten(), vec![(&'c', four_by_four(0, 4))]);
+        tree.remove(c);
+        assert_eq!(tree.leaves(), vec![0]);
+
+        let d = tree.insert(4, 4, 'd')?;
+        assert_eq!(tree.get(d), Some((&'d', four_by_four(0, 0))));
+        Ok(())
+    }
+
+    #[test]
+    fn insert_reuses_removed_area() -> Result<(), Box<dyn Error>> {
+        // a full tree where no partition can merge, every removed area has to
+        // take the next insert on its own
+        let mut tree = Tree2d::<char>::with_bounds(LeafChoice::MostSquare, 8, 8);
+        let mut handles = vec![];
+        for data in ['a', 'b', 'c', 'd'] {
+            handles.push(tree.insert(4, 4, data)?);
+        }
+        for handle in handles.iter_mut() {
+            let (data, bb) = tree.get(*handle).map(|(data, bb)| (*data, bb)).unwrap();
+            tree.remove(*handle);
+            assert!(tree.insert(5, 4, data).is_err());
+            *handle = tree.insert(4, 4, data)?;
+            assert_eq!(tree.get(*handle), Some((&data, bb)));
+        }
+
+        // the reused area frees up again, and merges back once everything's gone
+        let (_, bb) = tree.get(handles[0]).unwrap();
+        tree.remove(handles[0]);
+        let smaller = tree.insert(2, 2, 'e')?;
+        assert_eq!(
+            tree.get(smaller).map(|(_, at)| (at.x, at.y)),
+            Some((bb.x, bb.y))
+        );
+        tree.remove(smaller);
+        for handle in &handles[1..] {
+            tree.remove(*handle);
+        }
+        assert_eq!(tree.leaves(), vec![0]);
+        Ok(())
+    }
+
+    #[test]
+    fn defragment_reports_moves() -> Result<(), Box<dyn Error>> {
+        let mut tree = Tree2d::<char>::new();
+        let a = tree.insert(4, 4, 'a')?;
+        let b = tree.insert(4, 4, 'b')?;
+        let c = tree.insert(4, 4, 'c')?;
+        tree.remove(a);
+        tree.remove(b);
+        assert_eq!(
+            tree.defragment(),
+            vec![Move {
+                handle: c,
+                from: four_by_four(0, 4),
+                to: four_by_four(0, 0),
+            }]
+        );
+        assert_eq!(tree.get(c), Some((&'c', four_by_four(0, 0))));
+        assert_eq!(tree.get_total_bounding_box(), four_by_four(0, 0));
+        assert_eq!(tree.defragment(), vec![]);
+
+        let e = tree.insert(4, 4, 'e')?;
+        assert_eq!(tree.get(e), Some((&'e', four_by_four(4, 0))));
+        tree.remove(c);
+        tree.remove(e);
+        assert_eq!(tree.leaves(), vec![0]);
+        Ok(())
+    }
+
+    // a benchmark, run with `cargo test --release -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn one_million_insertions() -> Result<(), Box<dyn Error>> {
+        for leaf_choice in LeafChoice::ALL {
+            let mut tree = Tree2d::<u32>::with_leaf_choice(leaf_choice);
+            let data = vec![
+                (
+                    DataSize {
+                        width: 1,
+                        height: 1
+                    },
+                    0
+                );
+                1_000_000
+            ];
+            let start = std::time::Instant::now();
+            tree.insert_all(data)?;
+            println!("{leaf_choice}: {:.2?}", start.elapsed());
+            let total_bb = tree.get_total_bounding_box();
+            assert!(total_bb.width as u64 * total_bb.height as u64 >= 1_000_000);
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,127 @@
+use std::error::Error;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use image::{imageops, RgbaImage};
+
+use crate::atlas::{self, Region, Rotation};
+
+pub fn extract(sheet: &RgbaImage, region: &Region) -> Result<RgbaImage, Box<dyn Error>> {
+    let (width, height) = region.sheet_size();
+    if region.x + width > sheet.width() || region.y + height > sheet.height() {
+        return Err(format!("{} lies outside the sheet", region.name).into());
+    }
+    let stored = imageops::crop_imm(sheet, region.x, region.y, width, height).to_image();
+    let sprite = match region.rotation {
+        Rotation::None => stored,
+        Rotation::Clockwise => imageops::rotate270(&stored),
+        Rotation::CounterClockwise => imageops::rotate90(&stored),
+    };
+
+    // put back the transparent border trimmed off the original
+    if region.source_size == (sprite.width(), sprite.height()) {
+        return Ok(sprite);
+    }
+    let mut source = RgbaImage::new(region.source_size.0, region.source_size.1);
+    imageops::replace(
+        &mut source,
+        &sprite,
+        region.offset.0 as i64,
+        region.offset.1 as i64,
+    );
+    Ok(source)
+}
+
+// sprite names may contain folders but never leave the output directory
+fn output_path(out_dir: &str, name: &str) -> PathBuf {
+    let mut path = PathBuf::from(out_dir);
+    for component in Path::new(name).components() {
+        if let Component::Normal(part) = component {
+            path.push(part);
+        }
+    }
+    if !name.to_lowercase().ends_with(".png") {
+        let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+        file_name.push(".png");
+        path.set_file_name(file_name);
+    }
+    path
+}
+
+pub fn run(sheet_file: &str, data_file: &str, out_dir: &str) -> Result<(), Box<dyn Error>> {
+    let sheet = image::open(sheet_file)?.to_rgba8();
+    let regions = atlas::read(data_file)?.regions;
+
+    for region in &regions {
+        let sprite = extract(&sheet, region)?;
+        let path = output_path(out_dir, &region.name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        sprite.save(&path)?;
+    }
+    println!("Unpacked {} sprites to {out_dir}", regions.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 3x2 sprite, red in the top left corner
+    fn sprite() -> RgbaImage {
+        let mut img = RgbaImage::from_pixel(3, 2, image::Rgba([0, 0, 255, 255]));
+        img.put_pixel(0, 0, image::Rgba([255, 0, 0, 255]));
+        img
+    }
+
+    fn region(rotation: Rotation) -> Region {
+        Region {
+            name: "a".to_owned(),
+            x: 1,
+            y: 1,
+            width: 3,
+            height: 2,
+            rotation,
+            offset: (1, 2),
+            source_size: (5, 5),
+        }
+    }
+
+    #[test]
+    fn extract_undoes_rotation_and_trimming() -> Result<(), Box<dyn Error>> {
+        for (rotation, stored) in [
+            (Rotation::None, sprite()),
+            (Rotation::Clockwise, imageops::rotate90(&sprite())),
+            (Rotation::CounterClockwise, imageops::rotate270(&sprite())),
+        ] {
+            let mut sheet = RgbaImage::new(8, 8);
+            imageops::replace(&mut sheet, &stored, 1, 1);
+            let source = extract(&sheet, &region(rotation))?;
+            assert_eq!((source.width(), source.height()), (5, 5));
+            assert_eq!(source.get_pixel(0, 0).0, [0, 0, 0, 0]);
+            assert_eq!(source.get_pixel(1, 2).0, [255, 0, 0, 255]);
+            assert_eq!(source.get_pixel(3, 3).0, [0, 0, 255, 255]);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn extract_outside_sheet() {
+        let sheet = RgbaImage::new(3, 3);
+        assert!(extract(&sheet, &region(Rotation::None)).is_err());
+    }
+
+    #[test]
+    fn output_paths() {
+        assert_eq!(output_path("out", "hero"), PathBuf::from("out/hero.png"));
+        assert_eq!(
+            output_path("out", "chars/hero.png"),
+            PathBuf::from("out/chars/hero.png")
+        );
+        assert_eq!(
+            output_path("out", "../../hero"),
+            PathBuf::from("out/hero.png")
+        );
+    }
+}
@@ -0,0 +1,305 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+
+use image::{DynamicImage, Rgba, RgbaImage};
+
+use crate::alpha;
+use crate::atlas::{self, Atlas, Region};
+use crate::packer::{self, ImportOptions};
+use crate::unpack;
+
+struct Rect {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+impl Rect {
+    fn of(region: &Region) -> Rect {
+        let (width, height) = region.sheet_size();
+        Rect {
+            x: region.x,
+            y: region.y,
+            width,
+            height,
+        }
+    }
+
+    // true when the rects are closer than `gap` pixels, or overlap when `gap` is 0
+    fn within(&self, other: &Rect, gap: u32) -> bool {
+        self.x < other.x + other.width + gap
+            && other.x < self.x + self.width + gap
+            && self.y < other.y + other.height + gap
+            && other.y < self.y + self.height + gap
+    }
+
+    fn same(&self, other: &Rect) -> bool {
+        (self.x, self.y, self.width, self.height) == (other.x, other.y, other.width, other.height)
+    }
+}
+
+fn check_layout(sheet: &RgbaImage, atlas: &Atlas, problems: &mut Vec<String>) {
+    let (width, height) = sheet.dimensions();
+    if let Some((w, h)) = atlas.size {
+        if (w, h) != (width, height) {
+            problems.push(format!(
+                "the sheet is {width}x{height} but the data records {w}x{h}"
+            ));
+        }
+    }
+
+    let padding = atlas.padding.unwrap_or(0);
+    let rects: Vec<Rect> = atlas.regions.iter().map(Rect::of).collect();
+    for (region, rect) in atlas.regions.iter().zip(rects.iter()) {
+        if rect.x + rect.width > width || rect.y + rect.height > height {
+            problems.push(format!("{} lies outside the sheet", region.name));
+        } else if rect.x < padding
+            || rect.y < padding
+            || rect.x + rect.width + padding > width
+            || rect.y + rect.height + padding > height
+        {
+            problems.push(format!(
+                "{} is closer to the edge of the sheet than the padding of {padding}",
+                region.name
+            ));
+        }
+    }
+
+    // regions sharing exactly the same rect are aliases of one image
+    for i in 0..rects.len() {
+        for j in i + 1..rects.len() {
+            let (a, b) = (&rects[i], &rects[j]);
+            if a.same(b) {
+                continue;
+            }
+            let (name_a, name_b) = (&atlas.regions[i].name, &atlas.regions[j].name);
+            if a.within(b, 0) {
+                problems.push(format!("{name_a} overlaps {name_b}"));
+            } else if a.within(b, padding) {
+                problems.push(format!(
+                    "{name_a} and {name_b} are closer than the padding of {padding}"
+                ));
+            }
+        }
+    }
+}
+
+// --alpha-bleed changes the colour of fully transparent pixels, which nothing
+// draws
+fn same_pixel(a: &Rgba<u8>, b: &Rgba<u8>) -> bool {
+    a == b || (a[3] == 0 && b[3] == 0)
+}
+
+// each source file is cut into sprites the way the packer cuts it, so frames,
+// layers and grid cells are compared with the part of the file they came from
+fn check_sources(
+    sheet: &RgbaImage,
+    atlas: &Atlas,
+    source_dir: &str,
+    options: ImportOptions,
+    problems: &mut Vec<String>,
+) -> Result<(), Box<dyn Error>> {
+    let mut paths = fs::read_dir(source_dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<PathBuf>, _>>()?;
+    paths.sort();
+    let mut sources: HashMap<String, (PathBuf, RgbaImage)> = HashMap::new();
+    for path in paths.into_iter().filter(|path| path.is_file()) {
+        for (name, mut img) in packer::load_sprites(options, &path)? {
+            if atlas.premultiplied_alpha {
+                img = DynamicImage::ImageRgba8(img.to_rgba8());
+                alpha::premultiply(&mut img);
+            }
+            sources.insert(atlas::normalise_name(&name), (path.clone(), img.to_rgba8()));
+        }
+    }
+
+    for region in &atlas.regions {
+        let name = atlas::normalise_name(&region.name);
+        let Some((path, source)) = sources.get(&name) else {
+            problems.push(format!(
+                "{} has no source file in {source_dir}",
+                region.name
+            ));
+            continue;
+        };
+        let Ok(sprite) = unpack::extract(sheet, region) else {
+            continue;
+        };
+        if source.dimensions() != sprite.dimensions() {
+            problems.push(format!(
+                "{} is {}x{} but {} is {}x{}",
+                region.name,
+                sprite.width(),
+                sprite.height(),
+                path.display(),
+                source.width(),
+                source.height()
+            ));
+            continue;
+        }
+        let different = sprite
+            .pixels()
+            .zip(source.pixels())
+            .filter(|(a, b)| !same_pixel(a, b))
+            .count();
+        if different > 0 {
+            problems.push(format!(
+                "{} differs from {} in {different} pixels",
+                region.name,
+                path.display()
+            ));
+        }
+    }
+    Ok(())
+}
+
+pub fn check(
+    sheet: &RgbaImage,
+    atlas: &Atlas,
+    source_dir: Option<&str>,
+    options: ImportOptions,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut problems = vec![];
+    check_layout(sheet, atlas, &mut problems);
+    if let Some(source_dir) = source_dir {
+        check_sources(sheet, atlas, source_dir, options, &mut problems)?;
+    }
+    Ok(problems)
+}
+
+// prints a report and returns whether the sheet matched its data
+pub fn run(
+    sheet_file: &str,
+    data_file: &str,
+    source_dir: Option<&str>,
+    options: ImportOptions,
+) -> Result<bool, Box<dyn Error>> {
+    let sheet = image::open(sheet_file)?.to_rgba8();
+    let atlas = atlas::read(data_file)?;
+    let problems = check(&sheet, &atlas, source_dir, options)?;
+    if atlas.size.is_none() || atlas.padding.is_none() {
+        println!("{data_file} doesn't record the sheet size and padding, they are not checked");
+    }
+
+    if problems.is_empty() {
+        println!(
+            "{sheet_file} matches {data_file}, {} sprites checked",
+            atlas.regions.len()
+        );
+        return Ok(true);
+    }
+    println!(
+        "{sheet_file} does not match {data_file}, {} problems:",
+        problems.len()
+    );
+    for problem in &problems {
+        println!("  {problem}");
+    }
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::atlas::Rotation;
+    use crate::config::Config;
+
+    fn region(name: &str, x: u32, y: u32, width: u32, height: u32) -> Region {
+        Region {
+            name: name.to_owned(),
+            x,
+            y,
+            width,
+            height,
+            rotation: Rotation::None,
+            offset: (0, 0),
+            source_size: (width, height),
+        }
+    }
+
+    fn atlas(regions: Vec<Region>) -> Atlas {
+        Atlas {
+            regions,
+            size: Some((16, 16)),
+            padding: Some(2),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn valid_layout() -> Result<(), Box<dyn Error>> {
+        let sheet = RgbaImage::new(16, 16);
+        let atlas = atlas(vec![
+            region("a", 2, 2, 4, 4),
+            region("b", 8, 2, 4, 4),
+            region("alias", 8, 2, 4, 4),
+        ]);
+        assert!(check(&sheet, &atlas, None, ImportOptions::default())?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn broken_layout() -> Result<(), Box<dyn Error>> {
+        let sheet = RgbaImage::new(16, 18);
+        let atlas = atlas(vec![
+            region("a", 2, 2, 4, 4),
+            region("overlapping", 4, 4, 4, 4),
+            region("close", 7, 2, 2, 2),
+            region("outside", 14, 14, 4, 4),
+            region("edge", 0, 10, 2, 2),
+        ]);
+        let problems = check(&sheet, &atlas, None, ImportOptions::default())?;
+        assert_eq!(
+            problems,
+            vec![
+                "the sheet is 16x18 but the data records 16x16",
+                "outside lies outside the sheet",
+                "edge is closer to the edge of the sheet than the padding of 2",
+                "a overlaps overlapping",
+                "a and close are closer than the padding of 2",
+                "overlapping and close are closer than the padding of 2",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn sheets_packed_with_alpha_flags_match_their_sources() -> Result<(), Box<dyn Error>> {
+        let mut img = RgbaImage::from_pixel(4, 4, Rgba([200, 100, 50, 255]));
+        img.put_pixel(0, 0, Rgba([0, 0, 0, 0]));
+        img.put_pixel(1, 0, Rgba([255, 255, 255, 0]));
+        img.put_pixel(2, 0, Rgba([255, 128, 10, 128]));
+        for flag in ["--alpha-bleed", "--premultiply-alpha"] {
+            let dir = std::env::temp_dir().join(format!("corral-verify{flag}"));
+            let source_dir = dir.join("src");
+            fs::create_dir_all(&source_dir)?;
+            img.save(source_dir.join("dot.png"))?;
+            let sheet_file = dir.join("d.png");
+            let args = [
+                "corral",
+                &source_dir.to_string_lossy(),
+                &sheet_file.to_string_lossy(),
+                flag,
+                "--meta",
+            ]
+            .map(|arg| arg.to_owned());
+            packer::run(Config::parse(&args)?)?;
+
+            let sheet = image::open(&sheet_file)?.to_rgba8();
+            let atlas = atlas::read(&dir.join("d.json").to_string_lossy())?;
+            let problems = check(
+                &sheet,
+                &atlas,
+                Some(&source_dir.to_string_lossy()),
+                ImportOptions::default(),
+            )?;
+            fs::remove_dir_all(&dir)?;
+            assert!(problems.is_empty(), "{flag}: {problems:?}");
+        }
+        Ok(())
+    }
+}